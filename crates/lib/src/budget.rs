@@ -0,0 +1,164 @@
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll, Waker};
+
+/// Amount of weight that the tasks are allowed to run with at once.
+///
+/// Tasks take their weight in the order of their requests, so a heavy task isn't starved by the light ones,
+/// which keeps the order of execution the same as the order of the queue.
+pub struct Budget {
+    limit:   usize,
+    free:    Cell<usize>,
+    next:    Cell<u64>,
+    waiting: RefCell<VecDeque<(u64, Option<Waker>)>>,
+}
+
+impl Budget {
+    pub fn new(limit: usize) -> Rc<Self> {
+        Rc::new(Budget {
+            limit,
+            free:    Cell::new(limit),
+            next:    Cell::new(0),
+            waiting: RefCell::new(VecDeque::new()),
+        })
+    }
+
+    /// Waits until the weight is available, a weight that is over the limit takes the whole budget.
+    pub fn take(self: &Rc<Self>, weight: usize) -> Take {
+        let ticket = self.next.get();
+        self.next.set(ticket + 1);
+
+        self.waiting.borrow_mut().push_back((ticket, None));
+
+        Take { budget: self.clone(), weight: weight.min(self.limit), ticket }
+    }
+
+    fn wake_first(&self) {
+        if let Some((_, Some(waker))) = self.waiting.borrow().front() {
+            waker.wake_by_ref();
+        }
+    }
+}
+
+pub struct Take {
+    budget: Rc<Budget>,
+    weight: usize,
+    ticket: u64,
+}
+
+impl Future for Take {
+    type Output = Grant;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let budget = &self.budget;
+        let mut waiting = budget.waiting.borrow_mut();
+
+        let first = waiting.front().is_some_and(|(ticket, _)| *ticket == self.ticket);
+
+        if first && budget.free.get() >= self.weight {
+            waiting.pop_front();
+            budget.free.set(budget.free.get() - self.weight);
+
+            drop(waiting);
+            budget.wake_first();
+
+            return Poll::Ready(Grant { budget: budget.clone(), weight: self.weight })
+        }
+
+        if let Some((_, waker)) = waiting.iter_mut().find(|(ticket, _)| *ticket == self.ticket) {
+            *waker = Some(cx.waker().clone());
+        }
+
+        Poll::Pending
+    }
+}
+
+impl Drop for Take {
+    /// Request that was given up, like the one of a cancelled task, lets the next one through.
+    fn drop(&mut self) {
+        let mut waiting = self.budget.waiting.borrow_mut();
+
+        if let Some(i) = waiting.iter().position(|(ticket, _)| *ticket == self.ticket) {
+            waiting.remove(i);
+
+            drop(waiting);
+            self.budget.wake_first();
+        }
+    }
+}
+
+/// Weight of a running task, that is given back to the budget when dropped.
+pub struct Grant {
+    budget: Rc<Budget>,
+    weight: usize,
+}
+
+impl Drop for Grant {
+    fn drop(&mut self) {
+        self.budget.free.set(self.budget.free.get() + self.weight);
+        self.budget.wake_first();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_lite::future;
+
+    use super::*;
+
+    fn poll(take: &mut Take) -> Option<Grant> {
+        future::block_on(future::poll_once(Pin::new(take)))
+    }
+
+    #[test]
+    fn weight_is_capped() {
+        let budget = Budget::new(2);
+
+        let heavy = poll(&mut budget.take(5)).unwrap();
+        let mut light = budget.take(1);
+
+        assert!(poll(&mut light).is_none());
+
+        drop(heavy);
+        assert!(poll(&mut light).is_some());
+    }
+
+    #[test]
+    fn requests_are_granted_in_order() {
+        let budget = Budget::new(3);
+
+        let first = poll(&mut budget.take(2)).unwrap();
+        let mut second = budget.take(2);
+        let mut third = budget.take(1);
+
+        // Weight of the third one is free, but it would overtake the second
+        assert!(poll(&mut second).is_none());
+        assert!(poll(&mut third).is_none());
+
+        drop(first);
+
+        assert!(poll(&mut third).is_none());
+        assert!(poll(&mut second).is_some());
+        assert!(poll(&mut third).is_some());
+    }
+
+    #[test]
+    fn cancelled_request_lets_the_next_one_through() {
+        let budget = Budget::new(1);
+
+        let first = poll(&mut budget.take(1)).unwrap();
+        let mut second = budget.take(1);
+        let mut third = budget.take(1);
+
+        assert!(poll(&mut second).is_none());
+        assert!(poll(&mut third).is_none());
+
+        drop(second);
+        drop(first);
+
+        assert!(poll(&mut third).is_some());
+    }
+}
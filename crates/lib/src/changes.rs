@@ -0,0 +1,366 @@
+use std::collections::HashMap;
+use std::ffi::OsString;
+
+use futures_lite::{stream, Stream, StreamExt};
+
+use crate::error::{Error, MonitorError};
+use crate::monitor::{self, Event, Monitor, StateEvent};
+use crate::path::{Kind, Root};
+use crate::{Reader, State, Status, Task};
+
+/// What has happened to the runs, according to their states.
+#[derive(Debug)]
+pub enum Change {
+    /// Runner has started a run of a directory
+    RunStarted(State),
+    /// Task of an active run has changed its status, or has appeared with one
+    TaskChanged {
+        root:      Root,
+        file_name: OsString,
+        /// Position of the task in the state
+        index:     usize,
+        old:       Option<Status>,
+        new:       Status,
+        task:      Box<Task>,
+    },
+    /// Run is over and its final state is in the persistent storage
+    RunFinished(State),
+    /// Runner has stopped without reporting the end of a run
+    Dangling(State),
+    /// Everything about a state was removed
+    Removed(State),
+    /// Output file that is watched with [`Monitor::watch_output`] was modified
+    Output,
+    /// Changes were lost, so the states have to be listed again
+    Resync,
+}
+
+/// Active or dangling run that is followed to tell what has changed.
+struct Run {
+    reader:   Reader,
+    tasks:    Vec<Task>,
+    dangling: bool,
+}
+
+impl Run {
+    fn new(root: &Root, file_name: &OsString) -> Self {
+        Run {
+            reader:   Reader::new(root.runtime_dir().join(file_name)),
+            tasks:    Vec::new(),
+            dangling: false,
+        }
+    }
+}
+
+/// State of a root, by the position of the root.
+type Key = (usize, OsString);
+
+/// Last known tasks of every state that has a runtime file.
+struct Tracker {
+    roots: Vec<Root>,
+    runs:  HashMap<Key, Run>,
+    known: Vec<Key>,
+}
+
+impl Tracker {
+    fn new(roots: Vec<Root>) -> Self {
+        Tracker { roots, runs: HashMap::new(), known: Vec::new() }
+    }
+
+    /// Lists the states, so that only what changes afterwards is reported.
+    fn resync(&mut self) -> Result<(), Error> {
+        self.runs.clear();
+        self.known.clear();
+
+        for (i, root) in self.roots.iter().enumerate() {
+            let states = match crate::states_of(root) {
+                Ok(states) => states,
+                // States of other users might be unreadable
+                Err(_) if !root.is_current() => continue,
+                Err(e) => return Err(e),
+            };
+
+            for state in states {
+                let key = (i, state.file_name.clone());
+
+                if state.runtime {
+                    let mut run = Run::new(root, &state.file_name);
+
+                    run.tasks = run.reader.read().unwrap_or_default();
+                    run.dangling = !state.running;
+
+                    self.runs.insert(key.clone(), run);
+                }
+
+                self.known.push(key);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn handle(&mut self, event: Result<StateEvent, MonitorError>) -> Vec<Result<Change, MonitorError>> {
+        let mut changes = Vec::new();
+
+        let event = match event {
+            Ok(event) => event,
+            Err(e) => return vec![Err(e)],
+        };
+
+        let key = (event.root, event.file_name);
+
+        match (event.event, event.kind) {
+            (Event::Resync, _) => match self.resync() {
+                Ok(_) => changes.push(Ok(Change::Resync)),
+                Err(e) => changes.push(Err(e.into())),
+            },
+            (Event::Modified, Kind::Output) => changes.push(Ok(Change::Output)),
+            (Event::New | Event::Modified | Event::Closed, Kind::Runtime) => self.update(key, &mut changes),
+            (Event::Removed, Kind::Runtime) => self.finish(key, &mut changes),
+            // Lock is released when the runner exits, even if it wasn't able to remove the file
+            (Event::Removed | Event::Closed, Kind::Lock) => self.release(key, &mut changes),
+            // Final state of a run that wasn't seen, like the one that was repaired
+            (Event::New, Kind::Persistent) if !self.runs.contains_key(&key) && !self.known.contains(&key) => {
+                let state = self.state(&key);
+                self.known.push(key);
+                changes.push(Ok(Change::RunFinished(state)));
+            },
+            (Event::Removed, Kind::Persistent) => self.remove(key, &mut changes),
+            _ => {},
+        }
+
+        changes
+    }
+
+    fn update(&mut self, key: Key, changes: &mut Vec<Result<Change, MonitorError>>) {
+        let root = &self.roots[key.0];
+
+        // Runner that has started over a dangling state replaces it with a new run
+        if self.runs.get(&key).is_none_or(|run| run.dangling) {
+            self.runs.insert(key.clone(), Run::new(root, &key.1));
+
+            if !self.known.contains(&key) {
+                self.known.push(key.clone());
+            }
+
+            changes.push(Ok(Change::RunStarted(state(root, key.1.clone()))));
+        }
+
+        let run = self.runs.get_mut(&key).expect("run was just inserted");
+
+        match run.reader.read() {
+            Ok(tasks) => {
+                diff(root, &key.1, &run.tasks, &tasks, changes);
+                run.tasks = tasks;
+            },
+            // State might be gone already
+            Err(Error::NotFound(_)) => {},
+            Err(e) => changes.push(Err(e.into())),
+        }
+    }
+
+    fn finish(&mut self, key: Key, changes: &mut Vec<Result<Change, MonitorError>>) {
+        let Some(run) = self.runs.remove(&key) else {
+            return self.remove(key, changes)
+        };
+
+        let root = &self.roots[key.0];
+        let persistent_path = root.persistent_dir().join(&key.1);
+
+        if !persistent_path.is_file() {
+            return self.remove(key, changes)
+        }
+
+        match crate::parse(&persistent_path) {
+            Ok(tasks) => diff(root, &key.1, &run.tasks, &tasks, changes),
+            Err(e) => changes.push(Err(e.into())),
+        }
+
+        let mut state = self.state(&key);
+
+        // Lock is released only after the runtime state is removed
+        state.running = false;
+
+        changes.push(Ok(Change::RunFinished(state)));
+    }
+
+    fn release(&mut self, key: Key, changes: &mut Vec<Result<Change, MonitorError>>) {
+        let state = self.state(&key);
+
+        let Some(run) = self.runs.get_mut(&key) else {
+            return
+        };
+
+        if !run.dangling && state.runtime && !state.running {
+            run.dangling = true;
+            changes.push(Ok(Change::Dangling(state)));
+        }
+    }
+
+    fn remove(&mut self, key: Key, changes: &mut Vec<Result<Change, MonitorError>>) {
+        let state = self.state(&key);
+
+        if state.exists() {
+            return
+        }
+
+        if let Some(i) = self.known.iter().position(|known| *known == key) {
+            self.known.swap_remove(i);
+            changes.push(Ok(Change::Removed(state)));
+        }
+    }
+
+    #[inline]
+    fn state(&self, key: &Key) -> State {
+        state(&self.roots[key.0], key.1.clone())
+    }
+}
+
+/// What is there of a state right now.
+fn state(root: &Root, file_name: OsString) -> State {
+    let mut state = State::new(file_name).root(root.clone());
+
+    state.runtime = root.runtime_dir().join(&state.file_name).is_file();
+    state.persistent = root.persistent_dir().join(&state.file_name).is_file();
+    state.running = crate::is_locked(root.lock_dir().join(&state.file_name)).unwrap_or(false);
+
+    state
+}
+
+fn diff(root: &Root, file_name: &OsString, old: &[Task], new: &[Task], changes: &mut Vec<Result<Change, MonitorError>>) {
+    for (index, task) in new.iter().enumerate() {
+        let old = old.get(index)
+            .filter(|old| old.path == task.path)
+            .map(|old| old.status);
+
+        if old != Some(task.status) {
+            changes.push(Ok(Change::TaskChanged {
+                root: root.clone(),
+                file_name: file_name.clone(),
+                index,
+                old,
+                new: task.status,
+                task: Box::new(task.clone()),
+            }));
+        }
+    }
+}
+
+/// Watches the states of the roots like [`monitor::monitor`] does, but tells what has changed for the runs and their tasks,
+/// comparing every state with its previous version.
+pub fn changes(roots: Vec<Root>) -> Result<(Monitor, impl Stream<Item = Result<Change, MonitorError>> + Unpin), MonitorError> {
+    let (monitor, events) = monitor::monitor(&roots)?;
+
+    // States are listed after the watches are placed, so that nothing is missed in between
+    let mut tracker = Tracker::new(roots);
+    tracker.resync()?;
+
+    let stream = events.flat_map(move |event| stream::iter(tracker.handle(event)));
+
+    Ok((monitor, stream))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    /// Tracker of a root in a temporary directory, that is followed by the events of a single state.
+    struct Fixture {
+        dir:     PathBuf,
+        tracker: Tracker,
+    }
+
+    impl Fixture {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("run-stars-changes-{name}-{}", std::process::id()));
+            let root = Root::at("test", dir.join("run"), dir.join("state"));
+
+            std::fs::create_dir_all(&dir).unwrap();
+            root.init_runtime_dir().unwrap();
+            root.init_persistent_dir().unwrap();
+
+            let mut tracker = Tracker::new(vec![root]);
+            tracker.resync().unwrap();
+
+            Fixture { dir, tracker }
+        }
+
+        fn write(&self, kind: Kind, statuses: &[Status]) {
+            let root = &self.tracker.roots[0];
+
+            let p = match kind {
+                Kind::Runtime => root.runtime_dir().join("state"),
+                _ => root.persistent_dir().join("state"),
+            };
+
+            let tasks: Vec<_> = statuses.iter().enumerate()
+                .map(|(i, &status)| Task { status, ..Task::new(PathBuf::from(format!("/etc/{i}"))) })
+                .collect();
+
+            let mut buffer = Vec::new();
+            crate::write::write(std::io::sink(), &mut buffer, &tasks, None).unwrap();
+            crate::write::replace(&p, &buffer, false).unwrap();
+        }
+
+        /// Short descriptions of the changes that an event has caused.
+        fn handle(&mut self, event: Event, kind: Kind) -> Vec<String> {
+            let event = StateEvent { event, kind, root: 0, file_name: OsString::from("state") };
+
+            self.tracker.handle(Ok(event)).into_iter()
+                .map(|change| match change.unwrap() {
+                    Change::RunStarted(_) => String::from("started"),
+                    Change::TaskChanged { index, old, new, .. } => format!("{index}: {:?} -> {}", old.map(Status::name), new.name()),
+                    Change::RunFinished(_) => String::from("finished"),
+                    Change::Dangling(_) => String::from("dangling"),
+                    change => format!("{change:?}"),
+                })
+                .collect()
+        }
+    }
+
+    impl Drop for Fixture {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    #[test]
+    fn started_changed_finished() {
+        let mut fixture = Fixture::new("finished");
+
+        fixture.write(Kind::Runtime, &[Status::Waiting, Status::Waiting]);
+        assert_eq!(fixture.handle(Event::New, Kind::Runtime), ["started", "0: None -> waiting", "1: None -> waiting"]);
+
+        fixture.write(Kind::Runtime, &[Status::Running, Status::Waiting]);
+        assert_eq!(fixture.handle(Event::Modified, Kind::Runtime), ["0: Some(\"waiting\") -> running"]);
+
+        // Nothing has changed since the last read
+        assert!(fixture.handle(Event::Modified, Kind::Runtime).is_empty());
+
+        fixture.write(Kind::Persistent, &[Status::Success, Status::Failure]);
+        std::fs::remove_file(fixture.tracker.roots[0].runtime_dir().join("state")).unwrap();
+
+        assert_eq!(fixture.handle(Event::Removed, Kind::Runtime), [
+            "0: Some(\"running\") -> success",
+            "1: Some(\"waiting\") -> failure",
+            "finished",
+        ]);
+    }
+
+    #[test]
+    fn dangling_and_started_over() {
+        let mut fixture = Fixture::new("dangling");
+
+        fixture.write(Kind::Runtime, &[Status::Running]);
+        assert_eq!(fixture.handle(Event::New, Kind::Runtime), ["started", "0: None -> running"]);
+
+        // Runner is gone without removing its runtime state
+        assert_eq!(fixture.handle(Event::Closed, Kind::Lock), ["dangling"]);
+        assert!(fixture.handle(Event::Removed, Kind::Lock).is_empty());
+
+        fixture.write(Kind::Runtime, &[Status::Waiting]);
+        assert_eq!(fixture.handle(Event::New, Kind::Runtime), ["started", "0: None -> waiting"]);
+    }
+}
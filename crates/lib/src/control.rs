@@ -0,0 +1,173 @@
+use std::ffi::OsStr;
+use std::fmt::Display;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use crate::error::{Error, ParseError};
+use crate::path::Root;
+
+/// Commands that a running instance accepts over its control socket,
+/// one per line in a form of `cancel <n>`, `rerun <n>`, `kill`, `status` or `subscribe`,
+/// where `<n>` is a line number of the task in the state file, starting from 0.
+///
+/// Each command is answered with a single line, `ok` or `error <message>`,
+/// except for the `status`, which is answered with a JSON object of all tasks.
+/// After the `subscribe` has been answered, every change of a task is sent as a JSON object on its own line.
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    /// Terminate a running task or skip it, if it's still waiting
+    Cancel(usize),
+    /// Execute a task that has already finished once more
+    Rerun(usize),
+    /// Cancel all tasks of the run
+    Kill,
+    /// Current state of all tasks
+    Status,
+    /// Stream of task changes until the run is over
+    Subscribe,
+}
+
+impl Display for Command {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Command::Cancel(i) => write!(f, "cancel {i}"),
+            Command::Rerun(i)  => write!(f, "rerun {i}"),
+            Command::Kill      => f.write_str("kill"),
+            Command::Status    => f.write_str("status"),
+            Command::Subscribe => f.write_str("subscribe"),
+        }
+    }
+}
+
+impl FromStr for Command {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || ParseError::Command(s.to_owned());
+
+        let mut parts = s.split_ascii_whitespace();
+
+        let command = match parts.next() {
+            Some("cancel")    => Command::Cancel(parts.next().and_then(|n| n.parse().ok()).ok_or_else(err)?),
+            Some("rerun")     => Command::Rerun(parts.next().and_then(|n| n.parse().ok()).ok_or_else(err)?),
+            Some("kill")      => Command::Kill,
+            Some("status")    => Command::Status,
+            Some("subscribe") => Command::Subscribe,
+            _                 => return Err(err()),
+        };
+
+        match parts.next() {
+            Some(_) => Err(err()),
+            None    => Ok(command),
+        }
+    }
+}
+
+pub const REPLY_OK: &str = "ok";
+pub const REPLY_ERROR: &str = "error";
+
+/// Location of a control socket that belongs to a running instance with a given state file name.
+///
+/// Socket is named by a hash of the file name, because the whole path to it has to fit into 108 bytes.
+pub fn socket_path(root: &Root, file_name: impl AsRef<OsStr>) -> PathBuf {
+    root.control_dir().join(crate::path::hash(file_name.as_ref().as_bytes()))
+}
+
+/// Sends a command that is answered with `ok` or `error <message>`, which are all of them except for the
+/// `status` and the `subscribe`, see [`status`] and [`subscribe`] for those.
+pub fn send(root: &Root, file_name: impl AsRef<OsStr>, command: &Command) -> Result<(), Error> {
+    if matches!(command, Command::Status | Command::Subscribe) {
+        return Err(Error::Refused(format!("{command} isn't answered with {REPLY_OK}")))
+    }
+
+    match exchange(root, file_name, command)?.0.trim_end() {
+        REPLY_OK => Ok(()),
+        reply => Err(Error::Refused(reply.to_owned())),
+    }
+}
+
+/// JSON object with the current state of all tasks of a running instance.
+pub fn status(root: &Root, file_name: impl AsRef<OsStr>) -> Result<String, Error> {
+    let (reply, _) = exchange(root, file_name, &Command::Status)?;
+
+    Ok(reply.trim_end().to_owned())
+}
+
+/// Changes of tasks of a running instance as JSON objects, one per line, until the run is over.
+pub fn subscribe(root: &Root, file_name: impl AsRef<OsStr>) -> Result<impl Iterator<Item = Result<String, Error>>, Error> {
+    let p = socket_path(root, &file_name);
+    let (reply, reader) = exchange(root, file_name, &Command::Subscribe)?;
+
+    if reply.trim_end() != REPLY_OK {
+        return Err(Error::Refused(reply.trim_end().to_owned()))
+    }
+
+    Ok(reader.lines().map(move |l| l.map_err(|io| Error::Exchange { path: p.clone(), io })))
+}
+
+/// Sends a command and reads the first line of the reply, which is an error if it begins with `error`.
+fn exchange(root: &Root, file_name: impl AsRef<OsStr>, command: &Command) -> Result<(String, BufReader<UnixStream>), Error> {
+    let p = socket_path(root, file_name);
+
+    let mut stream = UnixStream::connect(&p)
+        .map_err(|io| Error::Connect { path: p.clone(), io })?;
+
+    let exchange_err = |io| Error::Exchange { path: p.clone(), io };
+
+    writeln!(stream, "{command}").map_err(exchange_err)?;
+
+    let mut reader = BufReader::new(stream);
+    let mut reply = String::new();
+    reader.read_line(&mut reply).map_err(exchange_err)?;
+
+    match reply.trim_end().split_once(' ') {
+        Some((REPLY_ERROR, message)) => Err(Error::Refused(message.to_owned())),
+        _ if reply.trim_end() == REPLY_ERROR => Err(Error::Refused(reply.trim_end().to_owned())),
+        _ if reply.is_empty() => Err(Error::Refused("connection was closed without a reply".to_owned())),
+        _ => Ok((reply, reader)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::net::UnixListener;
+
+    use super::*;
+
+    /// Answers every connection with the next reply, after the command was read.
+    fn serve(root: &Root, replies: &'static [&'static str]) {
+        let listener = UnixListener::bind(socket_path(root, "state")).unwrap();
+
+        std::thread::spawn(move || {
+            for (stream, reply) in listener.incoming().zip(replies) {
+                let mut stream = stream.unwrap();
+                BufReader::new(&stream).read_line(&mut String::new()).unwrap();
+
+                stream.write_all(reply.as_bytes()).unwrap();
+            }
+        });
+    }
+
+    #[test]
+    fn replies() {
+        let dir = std::env::temp_dir().join(format!("run-stars-control-{}", std::process::id()));
+        let root = Root::at("test", &dir, &dir);
+        root.init_control_dir().unwrap();
+
+        serve(&root, &["ok\n", "error no task\n", "{\"tasks\":[]}\n", "ok\n{\"task\":0}\n{\"task\":1}\n"]);
+
+        assert!(send(&root, "state", &Command::Kill).is_ok());
+        assert!(matches!(send(&root, "state", &Command::Cancel(9)), Err(Error::Refused(message)) if message == "no task"));
+        assert!(send(&root, "state", &Command::Status).is_err());
+
+        assert_eq!(status(&root, "state").unwrap(), "{\"tasks\":[]}");
+
+        let events: Vec<_> = subscribe(&root, "state").unwrap().map(Result::unwrap).collect();
+        assert_eq!(events, ["{\"task\":0}", "{\"task\":1}"]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -34,6 +34,60 @@ pub enum Error {
         path: PathBuf,
     },
 
+    #[error("couldn't write a state file ({path})\n{io}")]
+    WriteState {
+        io:   std::io::Error,
+        path: PathBuf,
+    },
+
+    #[error("couldn't execute a task ({path})\n{io}")]
+    Spawn {
+        io:   std::io::Error,
+        path: PathBuf,
+    },
+
+    #[error("couldn't wait for a task to exit ({path})\n{io}")]
+    Wait {
+        io:   std::io::Error,
+        path: PathBuf,
+    },
+
+    #[error("unable to create a file for the output ({path})\n{io}")]
+    Log {
+        io:   std::io::Error,
+        path: PathBuf,
+    },
+
+    #[error("couldn't rename a state that was named by an older version ({path})\n{io}")]
+    Rename {
+        io:   std::io::Error,
+        path: PathBuf,
+    },
+
+    #[error("couldn't move the states out of a location that was used by an older version ({path})\n{io}")]
+    Relocate {
+        io:   std::io::Error,
+        path: PathBuf,
+    },
+
+    #[error("couldn't remove a file ({path})\n{io}")]
+    Remove {
+        io:   std::io::Error,
+        path: PathBuf,
+    },
+
+    #[error("couldn't keep the path of a hashed state name ({path})\n{io}")]
+    WriteName {
+        io:   std::io::Error,
+        path: PathBuf,
+    },
+
+    #[error("couldn't write the history of runs ({path})\n{io}")]
+    WriteHistory {
+        io:   std::io::Error,
+        path: PathBuf,
+    },
+
     #[error("couldn't list state files inside of a directory ({path})\n{io}")]
     ListDir {
         io:   std::io::Error,
@@ -54,6 +108,14 @@ pub enum Error {
         path: PathBuf,
     },
 
+    #[error("{e:?} in a manifest ({path})\nline {num}: \"{line}\"")]
+    Manifest {
+        e:    ParseError,
+        num:  usize,
+        line: String,
+        path: PathBuf,
+    },
+
     #[error("couldn't parse a malformed line in a state file ({path})\nline {n}: \"{line}\"")]
     Malformed {
         n:    usize,
@@ -61,19 +123,49 @@ pub enum Error {
         path: PathBuf,
     },
 
+    #[error("couldn't connect to a control socket ({path}), is the run still active?\n{io}")]
+    Connect {
+        io:   std::io::Error,
+        path: PathBuf,
+    },
+
+    #[error("couldn't communicate over a control socket ({path})\n{io}")]
+    Exchange {
+        io:   std::io::Error,
+        path: PathBuf,
+    },
+
+    #[error("runner has refused a command\n{0}")]
+    Refused(String),
+
     #[error("no state file was found, expected at ({0})")]
     NotFound(PathBuf),
 
+    #[error("another run of the directory is in progress ({0})")]
+    Busy(PathBuf),
+
     #[error("expected a directory at {0}, but {0} is not a directory")]
     NotDirectory(PathBuf),
 
+    #[error("locations of the states can't be configured, because they are already in use")]
+    Configured,
+
+    #[error("namespace has to be a single directory name, but it's {0:?}")]
+    Namespace(String),
+
+    #[error("pool has to be a single file name, but it's {0:?}")]
+    Pool(String),
+
     #[error("expected state file ({0}) to be a file")]
-    NotFile(PathBuf)
+    NotFile(PathBuf),
+
+    #[error(transparent)]
+    Lock(#[from] LockError),
 }
 
 #[derive(Error)]
 pub enum ParseError {
-    #[error("couldn't parse a status key, expected 'S', 'F', 'R', 'W', got '{0}'")]
+    #[error("couldn't parse a status key, expected 'S', 'F', 'C', 'R', 'W', 'U', got '{0}'")]
     Status(String),
 
     #[error("couldn't parse an exit code, expected 0-255, got '{0}'")]
@@ -84,11 +176,90 @@ pub enum ParseError {
 
     #[error("couldn't parse a path to an executable, '{0}' is not a valid path")]
     Path(String),
+
+    #[error("couldn't parse a signal number, expected 1-255 or nothing, got '{0}'")]
+    Signal(String),
+
+    #[error("couldn't parse an amount of attempts, got '{0}'")]
+    Attempts(String),
+
+    #[error("couldn't parse a resource usage, expected seconds or kilobytes, got '{0}'")]
+    Usage(String),
+
+    #[error("couldn't parse columns, expected a known column name with path in the end, got '{0}'")]
+    Column(String),
+
+    #[error("couldn't parse a journal entry, expected a sequence number and an index of a task, got '{0}'")]
+    Journal(String),
+
+    #[error("journal entry is out of order, expected a sequence number {0}, got {1}")]
+    Sequence(u64, u64),
+
+    #[error("couldn't parse a control command, expected 'cancel <n>', 'rerun <n>', 'kill', 'status' or 'subscribe', got '{0}'")]
+    Command(String),
+
+    #[error("couldn't parse a symlink policy, expected 'skip', 'files' or 'follow', got '{0}'")]
+    Symlinks(String),
+
+    #[error("couldn't parse a class, expected 'class <name> <limit>', where limit is a positive number or 'nproc', got '{0}'")]
+    Class(String),
+
+    #[error("couldn't find a class '{0}', classes have to be declared before they are used")]
+    UnknownClass(String),
+
+    #[error("couldn't parse a weight, expected a positive number, got '{0}'")]
+    Weight(String),
+
+    #[error("couldn't parse a priority, expected a number, got '{0}'")]
+    Priority(String),
+
+    #[error("couldn't parse an option of a task, expected 'class=<name>', 'weight=<n>' or 'priority=<n>', got '{0}'")]
+    Rule(String),
+
+    #[error("couldn't parse an order, expected 'name', 'natural', 'manifest', 'duration' or 'shuffle', got '{0}'")]
+    Order(String),
+
+    #[error("couldn't parse a traversal, expected 'depth-first' or 'levels', got '{0}'")]
+    Traversal(String),
+}
+
+#[derive(Error)]
+pub enum MonitorError {
+    #[error("couldn't start watching for changes of the states\n{0}")]
+    Init(std::io::Error),
+
+    #[error("couldn't read the changes of the states\n{0}")]
+    Read(std::io::Error),
+
+    #[error("couldn't watch a directory ({path})\n{io}")]
+    Watch {
+        io:   std::io::Error,
+        path: PathBuf,
+    },
+
+    #[error(transparent)]
+    State(#[from] Error),
+}
+
+/// Reasons for a running run to refuse a command.
+#[derive(Error)]
+pub enum ControlError {
+    #[error("there is no task with index {0}")]
+    NoTask(usize),
+
+    #[error("task is not running")]
+    NotRunning,
+
+    #[error("task is already running")]
+    AlreadyRunning,
+
+    #[error("run has finished")]
+    Finished,
 }
 
 #[derive(Error)]
 pub enum LockError {
-    #[error("couldn't place a lock on a state file ({path}) for writing\n{io}")]
+    #[error("couldn't place a lock ({path}) that marks the run as active\n{io}")]
     Set {
         io: std::io::Error,
         path: PathBuf,
@@ -113,6 +284,20 @@ impl Debug for LockError {
     }
 }
 
+impl Debug for MonitorError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = beautify_newline(format!("{}", self));
+        f.write_str(s.as_str())
+    }
+}
+
+impl Debug for ControlError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = beautify_newline(format!("{}", self));
+        f.write_str(s.as_str())
+    }
+}
+
 impl Debug for ParseError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let s = beautify_newline(format!("{}", self));
@@ -1,47 +1,80 @@
+pub mod changes;
+pub mod control;
 pub mod error;
+pub mod migrate;
 pub mod path;
+pub mod repair;
 pub mod write;
 pub mod monitor;
+#[cfg(feature = "runner")]
+pub mod manifest;
+#[cfg(feature = "runner")]
+pub mod pool;
+#[cfg(feature = "runner")]
+pub mod run;
+
+#[cfg(feature = "runner")]
+mod budget;
+#[cfg(feature = "runner")]
+mod output;
+#[cfg(feature = "runner")]
+mod queue;
+#[cfg(feature = "runner")]
+mod wait;
 
 use core::panic;
-use std::ffi::OsString;
+use std::borrow::Cow;
+use std::ffi::{OsStr, OsString};
+use std::os::unix::ffi::OsStringExt;
 use std::fmt::Display;
 use std::fs::{DirEntry, File, FileType};
 use std::io::{BufRead, BufReader, ErrorKind};
 use std::os::fd::AsRawFd;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
 
 use jiff::Timestamp;
-use memchr::memchr_iter;
 
 use error::{Error, LockError, ParseError};
+use path::Root;
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct State {
+    #[cfg_attr(feature = "serde", serde(serialize_with = "serialize_lossy"))]
     pub file_name:  OsString,
     pub persistent: bool,
     pub runtime:    bool,
     pub running:    bool,
+    /// Locations of the state files, that tell whose they are
+    #[cfg_attr(feature = "serde", serde(rename = "owner", serialize_with = "serialize_owner"))]
+    pub root:       Root,
 }
 
 impl PartialEq for State {
     fn eq(&self, other: &Self) -> bool {
-        self.file_name == other.file_name
+        self.file_name == other.file_name && self.root == other.root
     }
 }
 
 impl State {
+    /// State of the effective user.
     pub fn new(s: OsString) -> Self {
         State {
             file_name:  s,
             persistent: false,
             runtime:    false,
             running:    false,
+            root:       Root::current().clone(),
         }
     }
 
+    pub fn root(mut self, root: Root) -> Self {
+        self.root = root;
+        self
+    }
+
     pub fn runtime(mut self) -> Self {
         self.runtime = true;
         self
@@ -64,34 +97,34 @@ impl State {
     }
 
     pub fn sub(&mut self, other: &State) {
-        // SAFETY: right shifting bool by bool is safe (0x01 >> 0x01, 0x01 >> 0x00)
-        unsafe {
-            self.persistent = std::mem::transmute::<u8, bool>(self.persistent as u8 >> other.persistent as u8);
-            self.runtime    = std::mem::transmute::<u8, bool>(self.runtime    as u8 >> other.runtime    as u8);
-            self.running    = std::mem::transmute::<u8, bool>(self.running    as u8 >> other.running    as u8);
-        }
+        self.persistent &= !other.persistent;
+        self.runtime    &= !other.runtime;
+        self.running    &= !other.running;
     }
 
     pub fn exists(&self) -> bool {
         self.persistent || self.runtime
     }
 
+    /// Directory of the state, or the file name itself if its path was lost.
     pub fn path(&self) -> PathBuf {
-        path::decode(self.file_name.clone())
+        self.root.path_of(&self.file_name)
+            .unwrap_or_else(|| PathBuf::from(&self.file_name))
     }
 
     pub fn has_persistent(&self) -> bool {
-        let p = path::persistent_dir().join(&self.file_name);
+        let p = self.root.persistent_dir().join(&self.file_name);
 
         std::fs::metadata(p).is_ok_and(|meta| meta.is_file())
     }
 
-    pub fn tasks(&self) -> Result<(Vec<Task>, Vec<Error>), Vec<Error>> {
+    /// Path to the state file that was modified last, runtime or persistent.
+    pub fn current(&self) -> Result<(PathBuf, Vec<Error>), Vec<Error>> {
         let mut current: Option<(PathBuf, SystemTime)> = None;
         let mut errors = Vec::new();
 
-        let runtime_path = path::runtime_dir().join(&self.file_name);
-        let persistent_path = path::persistent_dir().join(&self.file_name);
+        let runtime_path = self.root.runtime_dir().join(&self.file_name);
+        let persistent_path = self.root.persistent_dir().join(&self.file_name);
 
         let paths = [runtime_path, persistent_path];
 
@@ -100,7 +133,7 @@ impl State {
                Ok(meta) if meta.is_file() => {
                     let p_time = meta.modified().expect("modified field must be available to decide which state to read");
 
-                    if current.as_ref().map_or(true, |(_, current_time)| *current_time < p_time) {
+                    if current.as_ref().is_none_or(|(_, current_time)| *current_time < p_time) {
                         current = Some((p, p_time));
                     }
                 },
@@ -116,15 +149,62 @@ impl State {
             };
         }
 
-        if let Some((p, _)) = current {
-            match parse(&p) {
-                Ok(tasks) => return Ok((tasks, errors)),
-                Err(e) => errors.push(e),
-            }
+        match current {
+            Some((p, _)) => Ok((p, errors)),
+            None => Err(errors),
         }
+    }
+
+    pub fn tasks(&self) -> Result<(Vec<Task>, Vec<Error>), Vec<Error>> {
+        let (p, mut errors) = self.current()?;
+
+        match parse(&p) {
+            Ok(tasks) => Ok((tasks, errors)),
+            Err(e) => {
+                errors.push(e);
+                Err(errors)
+            },
+        }
+    }
+
+    /// Tasks of the previous runs, from the oldest to the newest.
+    pub fn history(&self) -> Result<Vec<Vec<Task>>, Error> {
+        let p = self.root.history_path(&self.file_name);
+
+        match p.is_file() {
+            true  => parse_runs(p),
+            false => Ok(Vec::new()),
+        }
+    }
+}
+
+/// Durations are written as fractional seconds, like in the state files.
+#[cfg(feature = "serde")]
+mod seconds {
+    use std::time::Duration;
+
+    use serde::{Deserialize, Deserializer, Serializer};
 
-        Err(errors)
+    pub fn serialize<S: Serializer>(d: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_f64(d.as_secs_f64())
     }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        let secs = f64::deserialize(deserializer)?;
+
+        Duration::try_from_secs_f64(secs).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(feature = "serde")]
+fn serialize_owner<S: serde::Serializer>(root: &Root, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&root.owner)
+}
+
+/// Paths that aren't valid UTF-8 are serialized with replacement characters, because JSON can't carry raw bytes.
+#[cfg(feature = "serde")]
+pub fn serialize_lossy<S: serde::Serializer>(s: impl AsRef<OsStr>, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&s.as_ref().to_string_lossy())
 }
 
 pub enum Directory {
@@ -132,49 +212,173 @@ pub enum Directory {
     Persistent,
 }
 
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct StateChange {
-    pub status: Status,
-    pub code:   u8,
-    pub time:   Timestamp,
+    pub status:  Status,
+    pub code:    u8,
+    pub signal:  Option<u8>,
+    pub usage:   Option<Usage>,
+    pub message: String,
+    pub time:    Timestamp,
 }
 
+impl StateChange {
+    pub fn new(status: Status) -> Self {
+        StateChange {
+            status,
+            code:    0,
+            signal:  None,
+            usage:   None,
+            message: String::new(),
+            time:    Timestamp::now(),
+        }
+    }
+
+    pub fn code(mut self, code: u8) -> Self {
+        self.code = code;
+        self
+    }
+
+    pub fn signal(mut self, signal: Option<u8>) -> Self {
+        self.signal = signal;
+        self
+    }
+
+    pub fn usage(mut self, usage: Usage) -> Self {
+        self.usage = Some(usage);
+        self
+    }
+
+    pub fn message(mut self, message: impl Into<String>) -> Self {
+        self.message = message.into();
+        self
+    }
+}
+
+/// Resources that were consumed by a task and all of its children.
+#[derive(Clone, Copy, Default, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Usage {
+    #[cfg_attr(feature = "serde", serde(with = "seconds"))]
+    pub user:    Duration,
+    #[cfg_attr(feature = "serde", serde(with = "seconds"))]
+    pub system:  Duration,
+    /// Peak resident set size in kilobytes
+    pub max_rss: u64,
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Task {
-    pub status: Status,
-    pub code:   u8,
-    pub time:   Timestamp,
-    pub path:   PathBuf,
+    pub status:   Status,
+    pub code:     u8,
+    pub time:     Timestamp,
+    pub signal:   Option<u8>,
+    pub attempts: u32,
+    pub start:    Option<Timestamp>,
+    pub end:      Option<Timestamp>,
+    pub usage:    Option<Usage>,
+    /// Short explanation of the last status, that is empty if there's nothing to say
+    pub message:  String,
+    #[cfg_attr(feature = "serde", serde(serialize_with = "serialize_lossy"))]
+    pub path:     PathBuf,
 }
 
 impl Task {
     pub fn new(p: PathBuf) -> Self {
         Self {
-            status: Status::Waiting,
-            code:   0,
-            time:   Timestamp::now(),
-            path:   p,
+            status:   Status::Waiting,
+            code:     0,
+            time:     Timestamp::now(),
+            signal:   None,
+            attempts: 0,
+            start:    None,
+            end:      None,
+            usage:    None,
+            message:  String::new(),
+            path:     p,
+        }
+    }
+
+    pub fn apply(&mut self, change: &StateChange) {
+        match change.status {
+            Status::Running => {
+                self.attempts += 1;
+                self.start = Some(change.time);
+                self.end = None;
+            },
+            Status::Success | Status::Failure | Status::Cancelled => {
+                self.end = Some(change.time);
+            },
+            Status::Waiting | Status::Unknown => {},
         }
+
+        self.status = change.status;
+        self.code = change.code;
+        self.signal = change.signal;
+        self.usage = change.usage;
+        self.message.clone_from(&change.message);
+        self.time = change.time;
+    }
+
+    /// Time between the start and the end of the last attempt, or until now if it's still running.
+    pub fn duration(&self) -> Option<Duration> {
+        let start = self.start?;
+        let end = self.end.unwrap_or_else(Timestamp::now);
+
+        u64::try_from(end.as_nanosecond() - start.as_nanosecond())
+            .ok()
+            .map(Duration::from_nanos)
     }
 }
 
-#[derive(PartialEq)]
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
 pub enum Status {
     Success,
     Failure,
+    Cancelled,
     Running,
     Waiting,
     Unknown,
 }
 
+impl Status {
+    pub const ALL: [Status; 6] = [
+        Status::Success,
+        Status::Failure,
+        Status::Cancelled,
+        Status::Running,
+        Status::Waiting,
+        Status::Unknown,
+    ];
+
+    /// Lowercase name that is also used for serialization.
+    pub fn name(self) -> &'static str {
+        match self {
+            Status::Success   => "success",
+            Status::Failure   => "failure",
+            Status::Cancelled => "cancelled",
+            Status::Running   => "running",
+            Status::Waiting   => "waiting",
+            Status::Unknown   => "unknown",
+        }
+    }
+}
+
 impl Display for Status {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         use std::fmt::Write;
 
         let c = match self {
-            Status::Success => 'S',
-            Status::Failure => 'F',
-            Status::Running => 'R',
-            Status::Waiting => 'W',
-            Status::Unknown => 'U',
+            Status::Success   => 'S',
+            Status::Failure   => 'F',
+            Status::Cancelled => 'C',
+            Status::Running   => 'R',
+            Status::Waiting   => 'W',
+            Status::Unknown   => 'U',
         };
 
         f.write_char(c)
@@ -188,6 +392,7 @@ impl FromStr for Status {
         match s {
             "S" => Ok(Status::Success),
             "F" => Ok(Status::Failure),
+            "C" => Ok(Status::Cancelled),
             "R" => Ok(Status::Running),
             "W" => Ok(Status::Waiting),
             "U" => Ok(Status::Unknown),
@@ -196,40 +401,95 @@ impl FromStr for Status {
     }
 }
 
+pub fn signal_name(signal: u8) -> Option<&'static str> {
+    const NAMES: [&str; 31] = [
+        "SIGHUP",  "SIGINT",    "SIGQUIT", "SIGILL",    "SIGTRAP", "SIGABRT", "SIGBUS",   "SIGFPE",
+        "SIGKILL", "SIGUSR1",   "SIGSEGV", "SIGUSR2",   "SIGPIPE", "SIGALRM", "SIGTERM",  "SIGSTKFLT",
+        "SIGCHLD", "SIGCONT",   "SIGSTOP", "SIGTSTP",   "SIGTTIN", "SIGTTOU", "SIGURG",   "SIGXCPU",
+        "SIGXFSZ", "SIGVTALRM", "SIGPROF", "SIGWINCH",  "SIGIO",   "SIGPWR",  "SIGSYS",
+    ];
+
+    NAMES.get((signal as usize).checked_sub(1)?).copied()
+}
+
+/// Short form of a duration that grows in units along with it, like `250ms`, `1.5s` or `2m 5s`.
+pub fn format_duration(d: Duration) -> String {
+    let secs = d.as_secs();
+
+    match secs {
+        0      => format!("{}ms", d.as_millis()),
+        1..60  => format!("{:.1}s", d.as_secs_f64()),
+        60..3600 => format!("{}m {}s", secs / 60, secs % 60),
+        _      => format!("{}h {}m {}s", secs / 3600, secs / 60 % 60, secs % 60),
+    }
+}
+
+/// States of the effective user, creating the directories for them if needed.
 pub fn states() -> Result<Vec<State>, Error> {
-    fn list_files(p: impl AsRef<Path>) -> Result<impl Iterator<Item = DirEntry>, Error> {
-        let p = p.as_ref();
+    let root = Root::current();
+
+    // States of an older version would be left behind, once the right location is in use
+    migrate::migrate_location(root)?;
+
+    root.init_runtime_dir()?;
+    root.init_persistent_dir()?;
+
+    states_of(root)
+}
+
+/// States of the system and of every user that can be read, along with the problems of the locations that can't.
+pub fn all_states(roots: &[Root]) -> (Vec<State>, Vec<Error>) {
+    let mut states = Vec::new();
+    let mut errors = Vec::new();
 
-        Ok(std::fs::read_dir(&p)
-            .map_err(|io| Error::ListDir { path: p.to_owned(), io })?
-            .flatten()
-            .filter(|f| f.file_type().as_ref().is_ok_and(FileType::is_file)))
+    for root in roots {
+        let result = match root.is_current() {
+            true  => self::states(),
+            false => states_of(root),
+        };
+
+        match result {
+            Ok(mut found) => states.append(&mut found),
+            Err(e) => errors.push(e),
+        }
     }
 
-    let runtime_path = path::init_runtime_dir()?;
+    (states, errors)
+}
 
-    let mut states: Vec<_> = list_files(runtime_path.as_path())?
-        .filter_map(|f| {
-            let mut state = State::new(f.file_name()).runtime();
+/// States of a single user, a missing directory has no states.
+pub fn states_of(root: &Root) -> Result<Vec<State>, Error> {
+    fn list_files(p: &Path) -> Result<Vec<DirEntry>, Error> {
+        match std::fs::read_dir(p) {
+            Ok(entries) => Ok(entries
+                .flatten()
+                .filter(|f| f.file_type().as_ref().is_ok_and(FileType::is_file))
+                .filter(|f| !path::is_temporary(f.file_name()))
+                .collect()),
+            Err(io) if io.kind() == ErrorKind::NotFound => Ok(Vec::new()),
+            Err(io) => Err(Error::ListDir { path: p.to_owned(), io }),
+        }
+    }
 
-            match is_locked(runtime_path.join(&state.file_name)) {
-                Ok(running) => {
-                    state.running = running;
-                    Some(state)
-                },
-                Err(io) if io.kind() == std::io::ErrorKind::NotFound => None,
-                Err(_) => Some(state),
-            }
+    let lock_dir = root.lock_dir();
+
+    let mut states: Vec<_> = list_files(root.runtime_dir())?
+        .into_iter()
+        .map(|f| {
+            let mut state = State::new(f.file_name()).root(root.clone()).runtime();
+
+            // Lock file is removed along with the lock, so a missing one means that nobody is running
+            state.running = is_locked(lock_dir.join(&state.file_name)).unwrap_or(false);
+            state
         })
         .collect();
 
-    let persistent_path = path::init_persistent_dir()?;
-
-    list_files(persistent_path)?
+    list_files(root.persistent_dir())?
+        .into_iter()
         .for_each(|f| match states.iter_mut().find(|state| state.file_name == f.file_name()) {
             Some(state) => state.persistent = true,
             None => {
-                let state = State::new(f.file_name()).persistent();
+                let state = State::new(f.file_name()).root(root.clone()).persistent();
                 states.push(state);
             }
         });
@@ -264,8 +524,139 @@ fn is_locked(p: impl AsRef<Path>) -> Result<bool, std::io::Error> {
 }
 
 pub const SPLIT_CHAR: char = ',';
+/// Encloses a field that contains a separator or a quote itself.
+pub const QUOTE_CHAR: char = '"';
+pub const HEADER_CHAR: char = '#';
+/// Beginning of a line that replaces a single task of the current run, followed by a sequence number and an index of the task.
+pub const JOURNAL_CHAR: char = '+';
+
+/// Amount of previous runs that are kept in the history.
+pub const HISTORY_LIMIT: usize = 20;
+
+/// Header that lists the columns of the lines that follow it.
+pub const COLUMNS_KEY: &str = "columns";
+/// Header that tells how the tasks that follow it were ordered, see [`Order`].
+pub const ORDER_KEY: &str = "order";
+
+/// How the files of a directory are ordered for the execution.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum Order {
+    /// Byte by byte comparison of the names
+    #[default]
+    Name,
+    /// Numbers inside of the names are compared by their value, so `2-foo` comes before `10-bar`
+    Natural,
+    /// Priority from the manifest, the highest first
+    Manifest,
+    /// Average duration from the history, the longest first
+    Duration,
+    /// Random order, that is different for every run
+    Shuffle,
+}
+
+impl Order {
+    pub fn name(self) -> &'static str {
+        match self {
+            Order::Name     => "name",
+            Order::Natural  => "natural",
+            Order::Manifest => "manifest",
+            Order::Duration => "duration",
+            Order::Shuffle  => "shuffle",
+        }
+    }
+}
+
+impl FromStr for Order {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        [Order::Name, Order::Natural, Order::Manifest, Order::Duration, Order::Shuffle].into_iter()
+            .find(|order| order.name() == s)
+            .ok_or_else(|| ParseError::Order(s.to_owned()))
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Column {
+    Status,
+    Code,
+    Time,
+    Signal,
+    Attempts,
+    Start,
+    End,
+    User,
+    System,
+    Rss,
+    Message,
+    /// Path that was written lossily, before the escaped one was introduced
+    Path,
+    EscapedPath,
+}
+
+/// Columns in the order they are written, path is the last one, because it might contain a separator.
+pub const COLUMNS: [Column; 12] = [
+    Column::Status,
+    Column::Code,
+    Column::Time,
+    Column::Signal,
+    Column::Attempts,
+    Column::Start,
+    Column::End,
+    Column::User,
+    Column::System,
+    Column::Rss,
+    Column::Message,
+    Column::EscapedPath,
+];
+
+/// Layout of the state files that were written before headers were introduced.
+const LEGACY_COLUMNS: [Column; 4] = [
+    Column::Status,
+    Column::Code,
+    Column::Time,
+    Column::Path,
+];
+
+impl Column {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Column::Status   => "status",
+            Column::Code     => "code",
+            Column::Time     => "time",
+            Column::Signal   => "signal",
+            Column::Attempts => "attempts",
+            Column::Start    => "start",
+            Column::End      => "end",
+            Column::User     => "user",
+            Column::System   => "system",
+            Column::Rss      => "rss",
+            Column::Message  => "message",
+            Column::Path     => "path",
+            Column::EscapedPath => "escaped_path",
+        }
+    }
+}
+
+impl FromStr for Column {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        COLUMNS.into_iter()
+            .chain([Column::Path])
+            .find(|c| c.name() == s)
+            .ok_or_else(|| ParseError::Column(s.to_owned()))
+    }
+}
 
 fn parse(p: impl AsRef<Path>) -> Result<Vec<Task>, Error> {
+    parse_runs(p).map(|mut runs| runs.pop().unwrap_or_default())
+}
+
+/// Every header that follows a task begins a new run, so that the history is just a concatenation of states.
+fn parse_runs(p: impl AsRef<Path>) -> Result<Vec<Vec<Task>>, Error> {
     let p = p.as_ref();
 
     if !p.is_file() {
@@ -273,40 +664,504 @@ fn parse(p: impl AsRef<Path>) -> Result<Vec<Task>, Error> {
     }
 
     let fd = File::open(p).map_err(|io| Error::Open { path: p.to_owned(), io })?;
-    let reader = BufReader::new(fd);
+    let mut reader = BufReader::new(fd);
 
-    let mut v = Vec::new();
+    let mut parser = Parser::new(p);
+    let mut l = String::new();
 
-    for (i, l) in reader.lines().enumerate() {
-        let l = l.map_err(|io| Error::Read { path: p.to_owned(), io })?;
-        let mut parts = memchr_iter(SPLIT_CHAR as u8, l.as_bytes());
+    loop {
+        l.clear();
 
-        let malformed_err = || Error::Malformed { n: i + 1, line: l.to_owned(), path: p.to_owned() };
+        match reader.read_line(&mut l).map_err(|io| Error::Read { path: p.to_owned(), io })? {
+            0 => break,
+            // Journal entry that is still being appended
+            _ if !l.ends_with('\n') && l.starts_with(JOURNAL_CHAR) => break,
+            _ => parser.feed(l.trim_end_matches('\n'))?,
+        }
+    }
+
+    Ok(parser.runs)
+}
 
-        let status = parts.next().ok_or_else(malformed_err)?;
-        let code = parts.next().ok_or_else(malformed_err)?;
-        let time = parts.next().ok_or_else(malformed_err)?;
+/// Parses a state line by line, so that it can be continued when something is appended.
+struct Parser {
+    path:    PathBuf,
+    runs:    Vec<Vec<Task>>,
+    columns: Vec<Column>,
+    /// Order of the last run, if it was written
+    order:   Option<Order>,
+    header:  bool,
+    /// Sequence number of the last journal entry in the current run
+    seq:     Option<u64>,
+    n:       usize,
+}
+
+impl Parser {
+    fn new(p: &Path) -> Self {
+        Parser {
+            path:    p.to_owned(),
+            runs:    Vec::new(),
+            columns: LEGACY_COLUMNS.to_vec(),
+            order:   None,
+            header:  true,
+            seq:     None,
+            n:       0,
+        }
+    }
 
-        // SAFETY: memchr_iter returns values inside of a slice 
-        let s_status = unsafe { l.get_unchecked(..status) };
-        let s_code   = unsafe { l.get_unchecked(status + 1..code) };
-        let s_time   = unsafe { l.get_unchecked(code + 1..time) };
-        let s_path   = unsafe { l.get_unchecked(time + 1..) };
+    fn feed(&mut self, l: &str) -> Result<(), Error> {
+        let i = self.n;
+        self.n += 1;
 
         let parse_err = |e: ParseError| Error::Parse {
             e,
             num:  i,
-            line: l.clone(),
-            path: p.to_owned()
+            line: l.to_owned(),
+            path: self.path.clone(),
+        };
+
+        let malformed = || Error::Malformed { n: i + 1, line: l.to_owned(), path: self.path.clone() };
+
+        if let Some(h) = l.strip_prefix(HEADER_CHAR) {
+            if !self.header {
+                self.columns = LEGACY_COLUMNS.to_vec();
+                self.order = None;
+                self.header = true;
+            }
+
+            // Order that is unknown to this version is just not shown
+            if let Some(value) = h.strip_prefix(ORDER_KEY).and_then(|v| v.strip_prefix(' ')) {
+                self.order = Order::from_str(value).ok();
+            }
+
+            if let Some(value) = h.strip_prefix(COLUMNS_KEY).and_then(|v| v.strip_prefix(' ')) {
+                self.columns = value.split(SPLIT_CHAR)
+                    .map(Column::from_str)
+                    .collect::<Result<_, _>>()
+                    .map_err(parse_err)?;
+
+                if !matches!(self.columns.last(), Some(Column::Path | Column::EscapedPath)) {
+                    return Err(parse_err(ParseError::Column(value.to_owned())))
+                }
+            }
+
+            return Ok(())
+        }
+
+        if let Some(entry) = l.strip_prefix(JOURNAL_CHAR) {
+            let fields = split(entry, self.columns.len() + 2).ok_or_else(malformed)?;
+
+            let seq = u64::from_str(&fields[0]).map_err(|_| parse_err(ParseError::Journal(fields[0].to_string())))?;
+            let index = usize::from_str(&fields[1]).map_err(|_| parse_err(ParseError::Journal(fields[1].to_string())))?;
+
+            if let Some(expected) = self.seq.map(|last| last + 1).filter(|&expected| expected != seq) {
+                return Err(parse_err(ParseError::Sequence(expected, seq)))
+            }
+
+            let task = parse_task(&self.columns, &fields[2..]).map_err(parse_err)?;
+
+            // Journal only changes the tasks that were already written
+            let current = match self.header {
+                true  => None,
+                false => self.runs.last_mut().and_then(|run| run.get_mut(index)),
+            };
+
+            *current.ok_or_else(malformed)? = task;
+            self.seq = Some(seq);
+
+            return Ok(())
+        }
+
+        if self.header {
+            self.runs.push(Vec::new());
+            self.header = false;
+            self.seq = None;
+        }
+
+        let fields = split(l, self.columns.len()).ok_or_else(malformed)?;
+
+        let task = parse_task(&self.columns, &fields).map_err(parse_err)?;
+
+        // SAFETY: run is pushed above, when the first task after a header is found
+        unsafe { self.runs.last_mut().unwrap_unchecked() }.push(task);
+
+        Ok(())
+    }
+}
+
+/// Follows a state file, parsing only what was appended since the last read.
+///
+/// State that was replaced as a whole is read again from the beginning.
+pub struct Reader {
+    path:     PathBuf,
+    identity: Option<(u64, u64)>,
+    offset:   u64,
+    partial:  Vec<u8>,
+    parser:   Parser,
+}
+
+impl Reader {
+    pub fn new(p: impl Into<PathBuf>) -> Self {
+        let path = p.into();
+
+        Reader {
+            parser:   Parser::new(&path),
+            path,
+            identity: None,
+            offset:   0,
+            partial:  Vec::new(),
+        }
+    }
+
+    #[inline]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Order of the last run that was read, states of older versions don't have it.
+    #[inline]
+    pub fn order(&self) -> Option<Order> {
+        self.parser.order
+    }
+
+    /// Tasks of the last run in the file.
+    pub fn read(&mut self) -> Result<Vec<Task>, Error> {
+        use std::io::{Read, Seek, SeekFrom};
+        use std::os::unix::fs::MetadataExt;
+
+        let p = self.path.as_path();
+
+        let mut fd = File::open(p).map_err(|io| match io.kind() {
+            ErrorKind::NotFound => Error::NotFound(p.to_owned()),
+            _ => Error::Open { path: p.to_owned(), io },
+        })?;
+
+        let meta = fd.metadata().map_err(|io| Error::Metadata { path: p.to_owned(), io })?;
+        let identity = Some((meta.dev(), meta.ino()));
+
+        if self.identity != identity || meta.len() < self.offset {
+            self.identity = identity;
+            self.offset = 0;
+            self.partial.clear();
+            self.parser = Parser::new(p);
+        }
+
+        fd.seek(SeekFrom::Start(self.offset))
+            .and_then(|_| fd.read_to_end(&mut self.partial))
+            .map(|n| self.offset += n as u64)
+            .map_err(|io| Error::Read { path: p.to_owned(), io })?;
+
+        let b = std::mem::take(&mut self.partial);
+        let mut lines = b.split_inclusive(|&c| c == b'\n').peekable();
+
+        while let Some(l) = lines.next() {
+            // Line without an end is still being written
+            if lines.peek().is_none() && !l.ends_with(b"\n") {
+                self.partial = l.to_vec();
+                break
+            }
+
+            let l = std::str::from_utf8(l)
+                .map_err(|e| Error::Read { path: p.to_owned(), io: std::io::Error::new(ErrorKind::InvalidData, e) })?;
+
+            if let Err(e) = self.parser.feed(l.trim_end_matches('\n')) {
+                // Everything has to be read again once the problem is fixed
+                self.identity = None;
+                return Err(e)
+            }
+        }
+
+        Ok(self.parser.runs.last().cloned().unwrap_or_default())
+    }
+}
+
+/// Splits raw contents of a history file into runs, without parsing them.
+pub(crate) fn split_runs(p: &Path) -> Result<Vec<Vec<u8>>, Error> {
+    let b = std::fs::read(p).map_err(|io| Error::Read { path: p.to_owned(), io })?;
+
+    let mut runs: Vec<Vec<u8>> = Vec::new();
+    let mut header = false;
+
+    for l in b.split_inclusive(|&c| c == b'\n') {
+        let is_header = l.first() == Some(&(HEADER_CHAR as u8));
+
+        match runs.last_mut() {
+            Some(run) if !is_header || header => run.extend_from_slice(l),
+            _ => runs.push(l.to_vec()),
+        }
+
+        header = is_header;
+    }
+
+    Ok(runs)
+}
+
+/// Splits a line into the exact amount of fields, where the last one takes the rest of the line,
+/// because paths in the states of older versions weren't quoted.
+///
+/// Quoted field that isn't closed properly is taken as it is, like a message of an older version that started with a quote.
+fn split(l: &str, n: usize) -> Option<Vec<Cow<'_, str>>> {
+    let mut fields = Vec::with_capacity(n);
+    let mut rest = l;
+
+    for i in 0..n {
+        let last = i + 1 == n;
+
+        if let Some((field, after)) = unquote(rest) {
+            match after.strip_prefix(SPLIT_CHAR) {
+                Some(after) if !last => {
+                    fields.push(Cow::Owned(field));
+                    rest = after;
+                    continue
+                },
+                None if after.is_empty() && last => {
+                    fields.push(Cow::Owned(field));
+                    return Some(fields)
+                },
+                _ => {},
+            }
+        }
+
+        match last {
+            true  => fields.push(Cow::Borrowed(rest)),
+            false => {
+                let (field, after) = rest.split_once(SPLIT_CHAR)?;
+                fields.push(Cow::Borrowed(field));
+                rest = after;
+            },
+        }
+    }
+
+    Some(fields)
+}
+
+/// Quoted field from the start of the line, along with what follows it.
+fn unquote(s: &str) -> Option<(String, &str)> {
+    let mut rest = s.strip_prefix(QUOTE_CHAR)?;
+    let mut field = String::new();
+
+    loop {
+        let (part, after) = rest.split_once(QUOTE_CHAR)?;
+        field.push_str(part);
+
+        match after.strip_prefix(QUOTE_CHAR) {
+            Some(after) => {
+                field.push(QUOTE_CHAR);
+                rest = after;
+            },
+            None => return Some((field, after)),
+        }
+    }
+}
+
+fn parse_task(columns: &[Column], fields: &[Cow<str>]) -> Result<Task, ParseError> {
+    fn optional<T>(s: &str, parse: impl FnOnce(&str) -> Option<T>, e: fn(String) -> ParseError) -> Result<Option<T>, ParseError> {
+        match s.is_empty() {
+            true  => Ok(None),
+            false => parse(s).map(Some).ok_or_else(|| e(s.to_owned())),
+        }
+    }
+
+    fn seconds(s: &str) -> Option<Duration> {
+        f64::from_str(s).ok().and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+    }
+
+    let mut task = Task::new(PathBuf::new());
+
+    for (column, s) in columns.iter().zip(fields) {
+        let s: &str = s;
+
+        match column {
+            Column::Status   => task.status = Status::from_str(s)?,
+            Column::Code     => task.code = u8::from_str(s).map_err(|_| ParseError::Code(s.to_owned()))?,
+            Column::Time     => task.time = Timestamp::from_str(s).map_err(|_| ParseError::Timestamp(s.to_owned()))?,
+            Column::Signal   => task.signal = optional(s, |s| u8::from_str(s).ok(), ParseError::Signal)?,
+            Column::Attempts => task.attempts = u32::from_str(s).map_err(|_| ParseError::Attempts(s.to_owned()))?,
+            Column::Start    => task.start = optional(s, |s| Timestamp::from_str(s).ok(), ParseError::Timestamp)?,
+            Column::End      => task.end = optional(s, |s| Timestamp::from_str(s).ok(), ParseError::Timestamp)?,
+            Column::User     => if let Some(user) = optional(s, seconds, ParseError::Usage)? {
+                task.usage.get_or_insert_with(Usage::default).user = user;
+            },
+            Column::System   => if let Some(system) = optional(s, seconds, ParseError::Usage)? {
+                task.usage.get_or_insert_with(Usage::default).system = system;
+            },
+            Column::Rss      => if let Some(rss) = optional(s, |s| u64::from_str(s).ok(), ParseError::Usage)? {
+                task.usage.get_or_insert_with(Usage::default).max_rss = rss;
+            },
+            Column::Message  => task.message = s.to_owned(),
+            Column::Path     => task.path = PathBuf::from_str(s).map_err(|_| ParseError::Path(s.to_owned()))?,
+            Column::EscapedPath => task.path = unescape(s).ok_or_else(|| ParseError::Path(s.to_owned()))?,
+        }
+    }
+
+    Ok(task)
+}
+
+/// Reverses the escaping of a path, see [`write::write`].
+fn unescape(s: &str) -> Option<PathBuf> {
+    let mut b = Vec::with_capacity(s.len());
+    let mut escaped = s.bytes();
+
+    while let Some(c) = escaped.next() {
+        match c {
+            b'\\' => match escaped.next()? {
+                b'\\' => b.push(b'\\'),
+                b'x' => {
+                    let digits = [escaped.next()?, escaped.next()?];
+                    b.push(u8::from_str_radix(std::str::from_utf8(&digits).ok()?, 16).ok()?);
+                },
+                _ => return None,
+            },
+            c => b.push(c),
+        }
+    }
+
+    Some(PathBuf::from(OsString::from_vec(b)))
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    fn round_trip(task: &Task) -> Task {
+        let mut buffer = Vec::new();
+        write::write(std::io::sink(), &mut buffer, std::slice::from_ref(task), None).unwrap();
+
+        parse_lines(std::str::from_utf8(&buffer).unwrap()).remove(0)
+    }
+
+    fn parse_lines(s: &str) -> Vec<Task> {
+        let mut parser = Parser::new(Path::new("state"));
+
+        for l in s.lines() {
+            parser.feed(l).unwrap();
+        }
+
+        parser.runs.pop().unwrap()
+    }
+
+    proptest! {
+        /// Every path and message is written into a single line and read back as it was,
+        /// except for the line breaks of a message.
+        #[test]
+        fn tasks_round_trip(b in prop::collection::vec(1u8..=255, 1..100), message in "[^\r\n]*") {
+            let mut task = Task::new(PathBuf::from(OsString::from_vec(b)));
+            task.message = message;
+
+            let parsed = round_trip(&task);
+
+            prop_assert_eq!(parsed.path, task.path);
+            prop_assert_eq!(parsed.message, task.message);
+        }
+    }
+
+    #[test]
+    fn quoted_fields() {
+        let mut task = Task::new(PathBuf::from("/etc/cron.daily/a,\"b\""));
+        task.message = String::from("exit code 1, \"oops\"");
+
+        let mut buffer = Vec::new();
+        write::write(std::io::sink(), &mut buffer, &[task], None).unwrap();
+
+        let line = std::str::from_utf8(&buffer).unwrap().lines().nth(1).unwrap();
+        assert!(line.ends_with(r#","exit code 1, ""oops""","/etc/cron.daily/a,""b""""#), "{line}");
+    }
+
+    #[test]
+    fn unquoted_fields_of_older_versions() {
+        let tasks = parse_lines("#columns status,code,time,message,path\nF,1,2024-09-06T03:33:08Z,\"x\" is missing,/etc/a,\"b\"");
+
+        assert_eq!(tasks[0].message, "\"x\" is missing");
+        assert_eq!(tasks[0].path, Path::new("/etc/a,\"b\""));
+    }
+
+    /// State of the tasks with the given statuses, followed by the journal entries that change the tasks of the given positions.
+    fn journal(tasks: &[Status], entries: &[(u64, usize, Status)]) -> String {
+        let task = |status| {
+            let mut task = Task::new(PathBuf::from("/etc/a"));
+            task.status = status;
+            task
         };
 
-        let status = Status::from_str(s_status).map_err(|_| parse_err(ParseError::Status(s_status.to_owned())))?;
-        let code = u8::from_str(s_code).map_err(|_| parse_err(ParseError::Code(s_code.to_owned())))?;
-        let time = Timestamp::from_str(s_time).map_err(|_| parse_err(ParseError::Timestamp(s_time.to_owned())))?;
-        let path = PathBuf::from_str(s_path).map_err(|_| parse_err(ParseError::Path(s_path.to_owned())))?;
+        let tasks: Vec<_> = tasks.iter().map(|&status| task(status)).collect();
+
+        let mut buffer = Vec::new();
+        write::write(std::io::sink(), &mut buffer, &tasks, None).unwrap();
 
-        v.push(Task { status, code, time, path });
+        let mut s = String::from_utf8(buffer).unwrap();
+
+        for &(seq, i, status) in entries {
+            let mut buffer = Vec::new();
+            write::write(std::io::sink(), &mut buffer, &[task(status)], None).unwrap();
+
+            let line = std::str::from_utf8(&buffer).unwrap().lines().nth(1).unwrap().to_owned();
+            s.push_str(&format!("{JOURNAL_CHAR}{seq}{SPLIT_CHAR}{i}{SPLIT_CHAR}{line}\n"));
+        }
+
+        s
     }
 
-    Ok(v)
+    fn statuses(tasks: &[Task]) -> Vec<Status> {
+        tasks.iter().map(|task| task.status).collect()
+    }
+
+    #[test]
+    fn journal_entries_replace_tasks() {
+        let s = journal(&[Status::Waiting, Status::Waiting], &[(1, 0, Status::Running), (2, 1, Status::Running), (3, 0, Status::Success)]);
+
+        assert_eq!(statuses(&parse_lines(&s)), [Status::Success, Status::Running]);
+    }
+
+    #[test]
+    fn journal_sequence_has_no_gaps() {
+        for entries in [
+            [(1, 0, Status::Running), (3, 0, Status::Success)],
+            [(2, 0, Status::Running), (1, 0, Status::Success)],
+            [(1, 0, Status::Running), (1, 0, Status::Success)],
+        ] {
+            let s = journal(&[Status::Waiting], &entries);
+
+            let mut parser = Parser::new(Path::new("state"));
+            let result = s.lines().try_for_each(|l| parser.feed(l));
+
+            assert!(matches!(result, Err(Error::Parse { e: ParseError::Sequence(..), .. })), "{s}");
+        }
+    }
+
+    #[test]
+    fn journal_entry_of_unknown_task() {
+        let s = journal(&[Status::Waiting], &[(1, 1, Status::Running)]);
+
+        let mut parser = Parser::new(Path::new("state"));
+        let result = s.lines().try_for_each(|l| parser.feed(l));
+
+        assert!(matches!(result, Err(Error::Malformed { .. })));
+    }
+
+    #[test]
+    fn truncated_journal_entry() {
+        let s = journal(&[Status::Waiting, Status::Waiting], &[(1, 0, Status::Running), (2, 1, Status::Running)]);
+        let (complete, last) = s.trim_end().rsplit_once('\n').unwrap();
+        let half = last.len() / 2;
+
+        let p = std::env::temp_dir().join(format!("run-stars-journal-{}", std::process::id()));
+        std::fs::write(&p, format!("{complete}\n{}", &last[..half])).unwrap();
+
+        let mut reader = Reader::new(&p);
+        let before = reader.read().map(|tasks| statuses(&tasks));
+        let history = parse_runs(&p).map(|mut runs| statuses(&runs.pop().unwrap()));
+
+        // Rest of the entry is picked up by the reader once it's appended
+        let mut fd = std::fs::OpenOptions::new().append(true).open(&p).unwrap();
+        std::io::Write::write_all(&mut fd, format!("{}\n", &last[half..]).as_bytes()).unwrap();
+
+        let after = reader.read().map(|tasks| statuses(&tasks));
+        std::fs::remove_file(&p).unwrap();
+
+        assert_eq!(before.unwrap(), [Status::Running, Status::Waiting]);
+        assert_eq!(history.unwrap(), [Status::Running, Status::Waiting]);
+        assert_eq!(after.unwrap(), [Status::Running, Status::Running]);
+    }
 }
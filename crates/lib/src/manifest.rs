@@ -0,0 +1,196 @@
+use std::io::ErrorKind;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+
+use crate::error::{Error, ParseError};
+
+/// Name of the manifest at the top of a directory, it's never executed as a task.
+pub const MANIFEST_NAME: &str = ".run-stars";
+
+/// Concurrency classes and weights of the tasks of a directory.
+///
+/// ```text
+/// # Class has a limit on the total weight of its tasks that run at once, or the amount of CPUs
+/// class io 1
+/// class cpu nproc
+///
+/// # Tasks of a path relative to the directory, or of every file under it, can be put in a class, weigh more than 1
+/// # and have a priority for the manifest order, when several lines match a task, the later ones win
+/// backup class=io priority=10
+/// db     class=io weight=2
+/// render class=cpu priority=-1
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Manifest {
+    pub classes: Vec<Class>,
+    pub rules:   Vec<Rule>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Class {
+    pub name:  String,
+    pub limit: NonZeroUsize,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Rule {
+    pub path:     PathBuf,
+    /// Position of the class in the manifest
+    pub class:    Option<usize>,
+    pub weight:   Option<NonZeroUsize>,
+    pub priority: Option<i64>,
+}
+
+impl Manifest {
+    /// Reads the manifest of a directory, which has none if the file doesn't exist.
+    pub fn read(dir: impl AsRef<Path>) -> Result<Option<Manifest>, Error> {
+        let path = dir.as_ref().join(MANIFEST_NAME);
+
+        let s = match std::fs::read_to_string(&path) {
+            Ok(s) => s,
+            Err(io) if io.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(io) => return Err(Error::Open { path, io }),
+        };
+
+        s.parse()
+            .map(Some)
+            .map_err(|(e, num, line)| Error::Manifest { e, num, line, path })
+    }
+
+    /// Class and weight of a task by its path relative to the directory.
+    pub fn task(&self, relative: impl AsRef<Path>) -> (Option<usize>, NonZeroUsize) {
+        let relative = relative.as_ref();
+
+        self.rules.iter()
+            .filter(|rule| relative.starts_with(&rule.path))
+            .fold((None, NonZeroUsize::MIN), |(class, weight), rule| {
+                (rule.class.or(class), rule.weight.unwrap_or(weight))
+            })
+    }
+
+    /// Priority of a task by its path relative to the directory, 0 by default.
+    pub fn priority(&self, relative: impl AsRef<Path>) -> i64 {
+        let relative = relative.as_ref();
+
+        self.rules.iter()
+            .filter(|rule| relative.starts_with(&rule.path))
+            .fold(0, |priority, rule| rule.priority.unwrap_or(priority))
+    }
+
+    fn parse_line(&mut self, l: &str) -> Result<(), ParseError> {
+        let mut parts = l.split_whitespace();
+
+        let first = parts.next().unwrap_or_default();
+
+        if first == "class" {
+            let (Some(name), Some(limit), None) = (parts.next(), parts.next(), parts.next()) else {
+                return Err(ParseError::Class(l.to_owned()))
+            };
+
+            let limit = match limit {
+                "nproc" => std::thread::available_parallelism().unwrap_or(NonZeroUsize::MIN),
+                limit => limit.parse().map_err(|_| ParseError::Class(l.to_owned()))?,
+            };
+
+            // Class that is declared again keeps its position, because the rules above refer to it
+            match self.classes.iter_mut().find(|class| class.name == name) {
+                Some(class) => class.limit = limit,
+                None => self.classes.push(Class { name: name.to_owned(), limit }),
+            }
+
+            return Ok(())
+        }
+
+        let mut rule = Rule { path: PathBuf::from(first), class: None, weight: None, priority: None };
+
+        for option in parts {
+            match option.split_once('=') {
+                Some(("class", name)) => {
+                    let class = self.classes.iter().position(|class| class.name == name)
+                        .ok_or_else(|| ParseError::UnknownClass(name.to_owned()))?;
+
+                    rule.class = Some(class);
+                },
+                Some(("weight", weight)) => {
+                    let weight = weight.parse().map_err(|_| ParseError::Weight(weight.to_owned()))?;
+                    rule.weight = Some(weight);
+                },
+                Some(("priority", priority)) => {
+                    let priority = priority.parse().map_err(|_| ParseError::Priority(priority.to_owned()))?;
+                    rule.priority = Some(priority);
+                },
+                _ => return Err(ParseError::Rule(option.to_owned())),
+            }
+        }
+
+        self.rules.push(rule);
+
+        Ok(())
+    }
+}
+
+impl std::str::FromStr for Manifest {
+    /// Error along with the number and the content of the line
+    type Err = (ParseError, usize, String);
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut manifest = Manifest::default();
+
+        for (num, l) in s.lines().enumerate() {
+            let l = l.trim();
+
+            if l.is_empty() || l.starts_with('#') {
+                continue
+            }
+
+            manifest.parse_line(l).map_err(|e| (e, num + 1, l.to_owned()))?;
+        }
+
+        Ok(manifest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MANIFEST: &str = "
+        # heavy ones
+        class io 1
+        class cpu 4
+
+        backup  class=io
+        db      class=io weight=2
+        db/dump weight=3 priority=5
+        render  class=cpu priority=-1
+    ";
+
+    #[test]
+    fn later_rules_win() {
+        let manifest: Manifest = MANIFEST.parse().unwrap();
+        let weight = |n| NonZeroUsize::new(n).unwrap();
+
+        assert_eq!(manifest.task("backup"), (Some(0), weight(1)));
+        assert_eq!(manifest.task("db/vacuum"), (Some(0), weight(2)));
+        assert_eq!(manifest.task("db/dump"), (Some(0), weight(3)));
+        assert_eq!(manifest.task("render/frames"), (Some(1), weight(1)));
+        assert_eq!(manifest.task("backups"), (None, weight(1)));
+
+        assert_eq!(manifest.priority("db/dump"), 5);
+        assert_eq!(manifest.priority("render/frames"), -1);
+        assert_eq!(manifest.priority("backup"), 0);
+    }
+
+    #[test]
+    fn malformed_lines() {
+        for (s, num) in [
+            ("class io", 1),
+            ("class io 0", 1),
+            ("backup class=io", 1),
+            ("class io 1\nbackup class=io weight=0", 2),
+            ("class io 1\nbackup io", 2),
+        ] {
+            assert_eq!(s.parse::<Manifest>().map_err(|(_, n, _)| n), Err(num), "{s}");
+        }
+    }
+}
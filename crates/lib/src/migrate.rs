@@ -0,0 +1,294 @@
+use std::collections::BTreeSet;
+use std::ffi::OsStr;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use crate::error::Error;
+use crate::path::{self, Root};
+use crate::write::RunLock;
+
+/// Renames everything that is known about the states, which were named by the legacy encoding, see [`path::encode`].
+/// States that are still used by a runner of an older version are skipped.
+///
+/// Returns the amount of migrated states.
+pub fn migrate(root: &Root) -> Result<usize, Error> {
+    let mut names = BTreeSet::new();
+
+    for dir in [root.runtime_dir().to_owned(), root.persistent_dir().to_owned(), root.history_dir(), root.logs_dir()] {
+        let entries = match std::fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(io) if io.kind() == ErrorKind::NotFound => continue,
+            Err(io) => return Err(Error::ListDir { path: dir, io }),
+        };
+
+        names.extend(entries.flatten()
+            .map(|entry| entry.file_name())
+            .filter(|file_name| path::is_legacy(file_name)));
+    }
+
+    let mut migrated = 0;
+
+    for file_name in names {
+        if migrate_state(root, &file_name)? {
+            migrated += 1;
+        }
+    }
+
+    Ok(migrated)
+}
+
+/// Location of the persistent states of the effective user that an older version has used by mistake, see [`xdg::legacy_state`],
+/// unless the states are kept somewhere else.
+pub fn legacy_location(root: &Root) -> Option<PathBuf> {
+    let legacy = Path::new(&xdg::legacy_state()?).join(path::DIR_NAME);
+    let current = Path::new(xdg::state()).join(path::DIR_NAME);
+
+    let moved = root.persistent_dir().starts_with(&current) && legacy.is_dir();
+
+    moved.then_some(legacy)
+}
+
+/// Moves the persistent states of the effective user out of the [`legacy_location`],
+/// merging them into the right one, if it's already in use.
+///
+/// Returns whether the states were moved.
+pub fn migrate_location(root: &Root) -> Result<bool, Error> {
+    let Some(legacy) = legacy_location(root) else {
+        return Ok(false)
+    };
+
+    let current = Path::new(xdg::state()).join(path::DIR_NAME);
+
+    if current.exists() {
+        merge(&legacy, &current)?;
+
+        std::fs::remove_dir(&legacy).map_err(|io| Error::Relocate { path: legacy, io })?;
+
+        return Ok(true)
+    }
+
+    if let Some(parent) = current.parent() {
+        std::fs::create_dir_all(parent).map_err(|io| Error::CreateLocation { path: parent.to_owned(), io })?;
+    }
+
+    std::fs::rename(&legacy, &current).map_err(|io| Error::Relocate { path: legacy, io })?;
+
+    Ok(true)
+}
+
+/// Moves every entry of a directory into another one, where the entries that exist already are newer,
+/// except for the histories, which are combined.
+fn merge(from: &Path, to: &Path) -> Result<(), Error> {
+    let entries = std::fs::read_dir(from).map_err(|io| Error::ListDir { path: from.to_owned(), io })?;
+    let history = from.file_name().is_some_and(|name| name == path::HISTORY_DIR_NAME);
+
+    for entry in entries {
+        let entry = entry.map_err(|io| Error::ListDir { path: from.to_owned(), io })?;
+
+        let old = entry.path();
+        let new = to.join(entry.file_name());
+
+        let is_dir = |p: &Path| std::fs::symlink_metadata(p).is_ok_and(|meta| meta.is_dir());
+
+        match is_dir(&old) && is_dir(&new) {
+            true => {
+                merge(&old, &new)?;
+                std::fs::remove_dir(&old).map_err(|io| Error::Remove { path: old, io })?;
+            },
+            false if history => prepend_history(&old, &new)?,
+            false => replace_unless_exists(&old, &new)?,
+        }
+    }
+
+    Ok(())
+}
+
+/// Migrates the state of a single directory, if it has one with a legacy name.
+pub fn migrate_dir(root: &Root, dir: impl AsRef<Path>) -> Result<bool, Error> {
+    let file_name = path::encode_legacy(dir.as_ref());
+
+    let exists = [
+        root.runtime_dir().join(&file_name),
+        root.persistent_dir().join(&file_name),
+        root.history_path(&file_name),
+        root.log_dir(&file_name),
+    ];
+
+    match exists.iter().any(|p| p.exists()) {
+        true  => migrate_state(root, &file_name),
+        false => Ok(false),
+    }
+}
+
+fn migrate_state(root: &Root, legacy: &OsStr) -> Result<bool, Error> {
+    // Runners of older versions have placed their lock on the runtime state itself
+    if crate::is_locked(root.runtime_dir().join(legacy)).unwrap_or(false) {
+        return Ok(false)
+    }
+
+    let Some(_legacy_lock) = RunLock::try_acquire(root, legacy)? else {
+        return Ok(false)
+    };
+
+    let dir = path::decode(legacy).expect("legacy names are always decoded");
+    let file_name = root.init_name(&dir)?;
+
+    // Runner of the same directory, that already uses the new name, is left alone until it's finished
+    let Some(_lock) = RunLock::try_acquire(root, &file_name)? else {
+        return Ok(false)
+    };
+
+    // States that were written with the new name are newer
+    for dir in [root.runtime_dir(), root.persistent_dir()] {
+        replace_unless_exists(&dir.join(legacy), &dir.join(&file_name))?;
+    }
+
+    let history = root.history_path(legacy);
+
+    if history.exists() {
+        prepend_history(&history, &root.init_history_dir()?.join(&file_name))?;
+    }
+
+    let logs = root.log_dir(legacy);
+
+    if logs.exists() {
+        replace_unless_exists(&logs, &root.log_dir(&file_name))?;
+    }
+
+    // Runner that has left the socket behind is gone
+    remove_file(&root.control_dir().join(legacy))?;
+
+    Ok(true)
+}
+
+/// History is a concatenation of runs, so the older ones just come first.
+fn prepend_history(older: &Path, newer: &Path) -> Result<(), Error> {
+    let mut b = match std::fs::read(older) {
+        Ok(b) => b,
+        Err(io) if io.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(io) => return Err(Error::Open { path: older.to_owned(), io }),
+    };
+
+    match std::fs::read(newer) {
+        Ok(newer) => b.extend(newer),
+        Err(io) if io.kind() == ErrorKind::NotFound => {},
+        Err(io) => return Err(Error::Open { path: newer.to_owned(), io }),
+    }
+
+    crate::write::replace(newer, &b, true)
+        .map_err(|io| Error::WriteHistory { path: newer.to_owned(), io })?;
+
+    remove_file(older)
+}
+
+fn replace_unless_exists(from: &Path, to: &Path) -> Result<(), Error> {
+    if std::fs::symlink_metadata(to).is_ok() {
+        let removed = match from.is_dir() {
+            true  => std::fs::remove_dir_all(from),
+            false => std::fs::remove_file(from),
+        };
+
+        return match removed {
+            Err(io) if io.kind() != ErrorKind::NotFound => Err(Error::Remove { path: from.to_owned(), io }),
+            _ => Ok(()),
+        }
+    }
+
+    match std::fs::rename(from, to) {
+        Err(io) if io.kind() != ErrorKind::NotFound => Err(Error::Rename { path: from.to_owned(), io }),
+        _ => Ok(()),
+    }
+}
+
+fn remove_file(p: &Path) -> Result<(), Error> {
+    match std::fs::remove_file(p) {
+        Err(io) if io.kind() != ErrorKind::NotFound => Err(Error::Remove { path: p.to_owned(), io }),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn locations_are_merged() {
+        let dir = std::env::temp_dir().join(format!("run-stars-merge-{}", std::process::id()));
+        let (legacy, current) = (dir.join("legacy"), dir.join("current"));
+
+        let write = |p: PathBuf, s: &str| {
+            std::fs::create_dir_all(p.parent().unwrap()).unwrap();
+            std::fs::write(p, s).unwrap();
+        };
+
+        write(legacy.join("@1%a"), "old a");
+        write(legacy.join("@1%b"), "old b");
+        write(legacy.join("history/@1%a"), "old runs\n");
+        write(legacy.join("logs/@1%a/task"), "old log");
+
+        write(current.join("@1%a"), "new a");
+        write(current.join("history/@1%a"), "new runs\n");
+
+        merge(&legacy, &current).unwrap();
+        std::fs::remove_dir(&legacy).unwrap();
+
+        let read = |p: &str| std::fs::read_to_string(current.join(p)).unwrap();
+
+        let merged = [read("@1%a"), read("@1%b"), read("history/@1%a"), read("logs/@1%a/task")];
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(merged, ["new a", "old b", "old runs\nnew runs\n", "old log"]);
+    }
+
+    #[test]
+    fn states_of_older_runners_are_skipped() {
+        use std::os::fd::AsRawFd;
+
+        let dir = std::env::temp_dir().join(format!("run-stars-old-runner-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let root = Root::at("test", dir.join("run"), dir.join("state"));
+        let legacy = path::encode_legacy("/etc/cron.daily");
+        let runtime = root.init_runtime_dir().unwrap().join(&legacy);
+
+        std::fs::write(&runtime, "").unwrap();
+
+        let fd = std::fs::OpenOptions::new().write(true).open(&runtime).unwrap();
+        let mut ready = [0; 2];
+
+        // Lock is placed by another process, because the locks of the same one don't conflict
+        let pid = unsafe {
+            assert_eq!(libc::pipe(ready.as_mut_ptr()), 0);
+
+            match libc::fork() {
+                0 => {
+                    let mut lock: libc::flock = std::mem::zeroed();
+                    lock.l_type = libc::F_WRLCK as _;
+
+                    libc::fcntl(fd.as_raw_fd(), libc::F_SETLK, std::ptr::from_mut(&mut lock));
+                    libc::write(ready[1], [0u8].as_ptr().cast(), 1);
+                    libc::pause();
+                    libc::_exit(0)
+                },
+                pid => pid,
+            }
+        };
+
+        unsafe { libc::read(ready[0], [0u8].as_mut_ptr().cast(), 1) };
+
+        let migrated = migrate(&root);
+        let kept = runtime.exists();
+
+        unsafe {
+            libc::kill(pid, libc::SIGKILL);
+            libc::waitpid(pid, std::ptr::null_mut(), 0);
+            libc::close(ready[0]);
+            libc::close(ready[1]);
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(migrated.unwrap(), 0);
+        assert!(kept);
+    }
+}
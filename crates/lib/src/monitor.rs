@@ -1,89 +1,307 @@
-use std::{ffi::OsString, io};
+use std::cell::RefCell;
+use std::ffi::{OsStr, OsString};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
 
-use futures_lite::{stream::Map, StreamExt};
-use inotify::{EventMask, EventStream, Inotify, WatchDescriptor, WatchMask};
+use futures_lite::{Stream, StreamExt};
+use inotify::{EventMask, Inotify, WatchDescriptor, WatchMask, Watches};
 
-use crate::path::{self, Kind};
+use crate::error::MonitorError;
+use crate::path::{self, Kind, Root};
 
-pub fn monitor() -> io::Result<Map<EventStream<[u8; 512]>, impl FnMut(io::Result<inotify::Event<OsString>>) -> StateEvent>>  {
-    let inotify = Inotify::init()?;
+/// Handle to the watches of a running monitor.
+pub struct Monitor {
+    watches: Watches,
+    output:  Rc<RefCell<Option<(WatchDescriptor, OsString)>>>,
+}
+
+impl Monitor {
+    /// Starts reporting modifications of a task output file, replacing the previous one.
+    pub fn watch_output(&mut self, p: impl AsRef<Path>) -> io::Result<()> {
+        let p = p.as_ref();
+
+        self.unwatch_output();
+
+        let wd = self.watches.add(p, WatchMask::MODIFY)?;
+        let file_name = p.file_name().unwrap_or_default().to_owned();
+
+        self.output.replace(Some((wd, file_name)));
 
-    let runtime_dir = crate::path::runtime_dir();
-    let persistent_dir = crate::path::persistent_dir();
+        Ok(())
+    }
 
-    const MASK: WatchMask = WatchMask::CREATE
-    .union(WatchMask::OPEN)
+    pub fn unwatch_output(&mut self) {
+        if let Some((wd, _)) = self.output.take() {
+            let _ = self.watches.remove(wd);
+        }
+    }
+}
+
+const MASK: WatchMask = WatchMask::CREATE
     .union(WatchMask::DELETE)
     .union(WatchMask::CLOSE_WRITE)
     .union(WatchMask::MOVED_FROM)
     .union(WatchMask::MOVED_TO)
-    .union(WatchMask::MODIFY);
+    .union(WatchMask::MODIFY)
+    .union(WatchMask::MOVE_SELF);
 
-    let mut watches = inotify.watches();
+/// Parent of a directory that is gone is watched until the directory appears again.
+const PARENT_MASK: WatchMask = WatchMask::CREATE.union(WatchMask::MOVED_TO);
 
-    let runtime_wd = watches.add(&runtime_dir, MASK)?;
-    let persistent_wd = watches.add(&persistent_dir, MASK)?;
+/// Directory with the states of a single kind.
+struct Dir {
+    kind:   Kind,
+    /// Position of the root that it belongs to
+    root:   usize,
+    path:   PathBuf,
+    wd:     Option<WatchDescriptor>,
+    /// Watch of the parent while the directory doesn't exist
+    parent: Option<WatchDescriptor>,
+}
 
-    let which = move |wd: WatchDescriptor| {
-        match wd {
-            wd if wd == runtime_wd => Kind::Runtime,
-            wd if wd == persistent_wd => Kind::Persistent,
-            _ => unreachable!("inotify has recieved an unknown watch descriptor"),
+/// Keeps the watches of state directories, even if they are removed and created again.
+struct Watcher {
+    watches: Watches,
+    dirs:    Vec<Dir>,
+}
+
+impl Watcher {
+    fn which(&self, wd: &WatchDescriptor) -> Option<usize> {
+        self.dirs.iter().position(|dir| dir.wd.as_ref() == Some(wd))
+    }
+
+    /// Watches a directory, or its parent if the directory doesn't exist yet.
+    ///
+    /// Returns whether the directory is watched.
+    fn watch(&mut self, i: usize) -> Result<bool, MonitorError> {
+        loop {
+            let path = self.dirs[i].path.clone();
+
+            match self.watches.add(&path, MASK) {
+                Ok(wd) => {
+                    self.dirs[i].wd = Some(wd);
+                    self.unwatch_parent(i);
+
+                    return Ok(true)
+                },
+                Err(io) if io.kind() != io::ErrorKind::NotFound => return Err(MonitorError::Watch { path, io }),
+                Err(_) => {},
+            }
+
+            if self.dirs[i].parent.is_some() {
+                return Ok(false)
+            }
+
+            let parent = path.parent().unwrap_or(&path).to_owned();
+
+            // Parent that is a state directory itself is already watched for new entries,
+            // while another watch of the same directory would replace its mask
+            let wd = match self.dirs.iter().find(|dir| dir.path == parent) {
+                Some(dir) => match &dir.wd {
+                    Some(wd) => wd.clone(),
+                    None => return Ok(false),
+                },
+                None => self.watches.add(&parent, PARENT_MASK)
+                    .map_err(|io| MonitorError::Watch { path: parent, io })?,
+            };
+
+            self.dirs[i].parent = Some(wd);
+
+            // Directory might have been created before the parent was watched
+            if !path.is_dir() {
+                return Ok(false)
+            }
         }
-    };
+    }
+
+    fn unwatch_parent(&mut self, i: usize) {
+        let Some(wd) = self.dirs[i].parent.take() else {
+            return
+        };
+
+        let shared = self.dirs.iter().any(|dir| dir.wd.as_ref() == Some(&wd) || dir.parent.as_ref() == Some(&wd));
 
-    let mask_err_msg = "watch mask doesn't include flags for directories";
-
-    let stream = inotify.into_event_stream([0; 512])?.map(move |event| {
-        let event = event.unwrap();
-
-        return match event.mask {
-            EventMask::MODIFY => StateEvent {
-                event: Event::Modified,
-                kind: which(event.wd),
-                file_name: event.name.expect(mask_err_msg),
-            },
-            EventMask::OPEN | EventMask::CREATE | EventMask::MOVED_TO => StateEvent {
-                event: Event::New,
-                kind: which(event.wd),
-                file_name: event.name.expect(mask_err_msg),
-            },
-            EventMask::DELETE | EventMask::MOVED_FROM => StateEvent {
-                event: Event::Removed,
-                kind: which(event.wd),
-                file_name: event.name.expect(mask_err_msg),
-            },
-            EventMask::CLOSE_WRITE => StateEvent {
-                event: Event::Closed,
-                kind: which(event.wd),
-                file_name: event.name.expect(mask_err_msg),
-            },
-            EventMask::Q_OVERFLOW => {
-                panic!("buffer for inotify events is not big enough to handle all events")
+        if !shared {
+            let _ = self.watches.remove(wd);
+        }
+    }
+
+    /// Directory that was removed or moved away is watched again once it's back,
+    /// along with the directories inside of it.
+    fn lost(&mut self, i: usize) -> Result<(), MonitorError> {
+        let path = self.dirs[i].path.clone();
+
+        let nested: Vec<_> = (0..self.dirs.len())
+            .filter(|&j| j == i || self.dirs[j].path.starts_with(&path))
+            .collect();
+
+        for &j in &nested {
+            if let Some(wd) = self.dirs[j].wd.take() {
+                let _ = self.watches.remove(wd);
             }
-            _ => StateEvent {
-                event: Event::Unknown,
-                kind: which(event.wd),
-                file_name: event.name.expect(mask_err_msg),
-            },
+
+            self.dirs[j].parent = None;
         }
-    });
 
-    Ok(stream)
+        nested.into_iter().try_for_each(|j| self.watch(j).map(|_| ()))
+    }
+
+    /// Watches the directories that have appeared inside of a watched parent.
+    ///
+    /// Returns whether any of them is watched again.
+    fn found(&mut self, wd: &WatchDescriptor, name: &OsStr) -> Result<bool, MonitorError> {
+        let found: Vec<_> = (0..self.dirs.len())
+            .filter(|&i| self.dirs[i].parent.as_ref() == Some(wd) && self.dirs[i].path.file_name() == Some(name))
+            .collect();
+
+        let mut watched = false;
+
+        for i in found {
+            watched |= self.watch(i)?;
+
+            // Directories inside of it could have been created already
+            for j in 0..self.dirs.len() {
+                if self.dirs[j].wd.is_none() && self.dirs[j].path.starts_with(&self.dirs[i].path) {
+                    self.dirs[j].parent = None;
+                    self.watch(j)?;
+                }
+            }
+        }
+
+        Ok(watched)
+    }
+}
+
+/// Watches the state directories of the given roots, that might not exist yet.
+///
+/// Stream yields [`Event::Resync`] when the events were lost or a directory was replaced,
+/// after which the states have to be listed again.
+pub fn monitor(roots: &[Root]) -> Result<(Monitor, impl Stream<Item = Result<StateEvent, MonitorError>> + Unpin), MonitorError> {
+    let inotify = Inotify::init().map_err(MonitorError::Init)?;
+
+    let dirs = roots.iter().enumerate().flat_map(|(root, r)| [
+        (Kind::Runtime, root, r.runtime_dir().to_owned()),
+        (Kind::Persistent, root, r.persistent_dir().to_owned()),
+        (Kind::Lock, root, r.lock_dir()),
+    ]);
+
+    let mut watcher = Watcher {
+        watches: inotify.watches(),
+        dirs:    dirs.map(|(kind, root, path)| Dir { kind, root, path, wd: None, parent: None }).collect(),
+    };
+
+    for i in 0..watcher.dirs.len() {
+        // Locks tell when a run is over, but the directory might be inaccessible for somebody else,
+        // as well as the states of other users
+        match watcher.watch(i) {
+            Err(_) if watcher.dirs[i].kind == Kind::Lock || !roots[watcher.dirs[i].root].is_current() => {},
+            result => { result?; },
+        }
+    }
+
+    let output = Rc::new(RefCell::new(None));
+    let monitor = Monitor { watches: watcher.watches.clone(), output: output.clone() };
+
+    let stream = inotify.into_event_stream([0; 512])
+        .map_err(MonitorError::Init)?
+        .map(move |event| {
+            let event = event.map_err(MonitorError::Read)?;
+
+            if event.mask.contains(EventMask::Q_OVERFLOW) {
+                return Ok(StateEvent::resync())
+            }
+
+            // Directory that has appeared again, its states were missed in the meantime
+            if event.mask.contains(EventMask::ISDIR) && event.mask.intersects(EventMask::CREATE | EventMask::MOVED_TO) {
+                if let Some(name) = &event.name {
+                    if watcher.found(&event.wd, name)? {
+                        return Ok(StateEvent::resync())
+                    }
+                }
+            }
+
+            let Some(i) = watcher.which(&event.wd) else {
+                // Output watch might have been replaced while events were still queued
+                return Ok(match &*output.borrow() {
+                    Some((wd, file_name)) if *wd == event.wd && event.mask == EventMask::MODIFY => StateEvent {
+                        event: Event::Modified,
+                        kind: Kind::Output,
+                        root: 0,
+                        file_name: file_name.clone(),
+                    },
+                    _ => StateEvent::unknown(Kind::Output),
+                })
+            };
+
+            let kind = watcher.dirs[i].kind.clone();
+            let root = watcher.dirs[i].root;
+
+            // Removed directory sends IGNORED, while a moved one is still watched at its new place
+            if event.mask.intersects(EventMask::IGNORED | EventMask::MOVE_SELF) {
+                watcher.lost(i)?;
+                return Ok(StateEvent::resync())
+            }
+
+            let Some(file_name) = event.name else {
+                return Ok(StateEvent::unknown(kind))
+            };
+
+            // States are written into temporary files first and then renamed
+            if path::is_temporary(&file_name) {
+                return Ok(StateEvent::unknown(kind))
+            }
+
+            let event = match event.mask {
+                EventMask::MODIFY => Event::Modified,
+                EventMask::CREATE | EventMask::MOVED_TO => Event::New,
+                EventMask::DELETE | EventMask::MOVED_FROM => Event::Removed,
+                EventMask::CLOSE_WRITE => Event::Closed,
+                _ => Event::Unknown,
+            };
+
+            Ok(StateEvent { event, kind, root, file_name })
+        });
+
+    Ok((monitor, stream))
 }
 
 #[derive(Debug)]
 pub struct StateEvent {
     pub event: Event,
     pub kind:  path::Kind,
+    /// Position of the root in the monitored ones
+    pub root:  usize,
     pub file_name:  OsString
 }
 
+impl StateEvent {
+    fn unknown(kind: Kind) -> Self {
+        StateEvent {
+            event: Event::Unknown,
+            kind,
+            root: 0,
+            file_name: OsString::new(),
+        }
+    }
+
+    fn resync() -> Self {
+        StateEvent {
+            event: Event::Resync,
+            kind: Kind::Runtime,
+            root: 0,
+            file_name: OsString::new(),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum Event {
     New,
     Modified,
     Removed,
     Closed,
+    /// Events were lost, so the states have to be listed again
+    Resync,
     Unknown,
 }
@@ -0,0 +1,149 @@
+use std::ffi::OsStr;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::process::{ChildStderr, Stdio};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_channel::Receiver;
+use futures_lite::future;
+
+use crate::error::Error;
+
+/// Longest stderr line that is kept for a message.
+const LINE_LIMIT: usize = 200;
+/// How long the rest of stderr is waited for after the task has exited.
+const LAST_LINE_TIMEOUT: Duration = Duration::from_millis(50);
+
+/// Opens a log for the output of a task, that is emptied on every start.
+///
+/// Stdout is written by the task directly and stderr is copied by the runner,
+/// both of them append to the file, so that they don't overwrite each other.
+pub fn log(file_name: impl AsRef<OsStr>, dir: impl AsRef<Path>, task: impl AsRef<Path>) -> Result<File, Error> {
+    let p = crate::path::log_path(file_name, dir, task);
+
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&p)
+        .and_then(|f| f.set_len(0).map(|_| f))
+        .map_err(|io| Error::Log { path: p, io })
+}
+
+pub fn stdout(log: Option<&File>) -> Stdio {
+    log.and_then(|f| f.try_clone().ok())
+        .map_or_else(Stdio::null, Stdio::from)
+}
+
+/// Last line of stderr of a task, that is remembered by a thread that copies it.
+pub struct Stderr {
+    line: Arc<Mutex<String>>,
+    /// Line that is sent once stderr is closed
+    last: Receiver<String>,
+}
+
+impl Stderr {
+    /// Waits a moment for the rest of stderr, because it might be still in the pipe when the task has exited,
+    /// or kept open by something that the task has left running in the background.
+    pub async fn last_line(&self) -> String {
+        let (s, timeout) = async_channel::bounded::<()>(1);
+
+        std::thread::spawn(move || {
+            std::thread::sleep(LAST_LINE_TIMEOUT);
+            drop(s);
+        });
+
+        let last = future::or(
+            async { self.last.recv().await.ok() },
+            async {
+                let _ = timeout.recv().await;
+                None
+            },
+        ).await;
+
+        last.unwrap_or_else(|| self.line.lock().map(|line| line.clone()).unwrap_or_default())
+    }
+}
+
+/// Copies stderr of a task into its log on a separate thread and remembers the last line.
+pub fn forward(stderr: Option<ChildStderr>, mut log: Option<File>) -> Stderr {
+    let (s, last) = async_channel::bounded(1);
+    let shared = Stderr { line: Arc::default(), last };
+
+    let Some(mut stderr) = stderr else {
+        return shared
+    };
+
+    let line = shared.line.clone();
+
+    std::thread::spawn(move || {
+        let mut b = [0; 4096];
+        let mut current = Vec::new();
+
+        loop {
+            let n = match stderr.read(&mut b) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(_) => break,
+            };
+
+            if let Some(f) = log.as_mut() {
+                let _ = f.write_all(&b[..n]);
+            }
+
+            for l in b[..n].split_inclusive(|&c| c == b'\n') {
+                let room = (LINE_LIMIT * 4).saturating_sub(current.len());
+                current.extend_from_slice(&l[..l.len().min(room)]);
+
+                if l.ends_with(b"\n") {
+                    remember(&line, &current);
+                    current.clear();
+                }
+            }
+        }
+
+        remember(&line, &current);
+
+        if let Ok(line) = line.lock() {
+            let _ = s.try_send(line.clone());
+        }
+    });
+
+    shared
+}
+
+fn remember(line: &Mutex<String>, l: &[u8]) {
+    let l = String::from_utf8_lossy(l);
+    let l = l.trim();
+
+    if l.is_empty() {
+        return
+    }
+
+    // Line ends up in the state, that is printed to the terminals of whoever reads it
+    if let Ok(mut line) = line.lock() {
+        *line = l.chars()
+            .take(LINE_LIMIT)
+            .map(|c| match c {
+                '\t' => ' ',
+                c if c.is_control() => '�',
+                c => c,
+            })
+            .collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn control_characters_are_replaced() {
+        let line = Mutex::default();
+        remember(&line, b"\x1b[31merror:\x1b[0m\tdisk is full\x07\r\n");
+
+        assert_eq!(*line.lock().unwrap(), "�[31merror:�[0m disk is full�");
+    }
+}
@@ -1,18 +1,319 @@
-use std::cell::UnsafeCell;
-use std::ffi::OsString;
-use std::os::unix::ffi::OsStringExt;
-use std::path::{Path, PathBuf};
-
-use memchr::memchr_iter;
+use std::ffi::{OsStr, OsString};
+use std::fs::DirBuilder;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::fs::DirBuilderExt;
+use std::path::{Component, Path, PathBuf};
+use std::sync::OnceLock;
 
 use crate::error::Error;
 
-const DIR_NAME: &'static str = "run_stars";
-
-#[derive(PartialEq, Eq, Debug)]
+pub(crate) const DIR_NAME: &str = "run_stars";
+const CONTROL_DIR_NAME: &str = "control";
+const LOG_DIR_NAME: &str = "logs";
+pub(crate) const HISTORY_DIR_NAME: &str = "history";
+const LOCK_DIR_NAME: &str = "locks";
+const NAMESPACE_DIR_NAME: &str = "namespaces";
+const NAME_DIR_NAME: &str = "names";
+const POOL_DIR_NAME: &str = "pools";
+
+/// Directory that holds the runtime states, instead of `$XDG_RUNTIME_HOME/run_stars`.
+pub const RUNTIME_DIR_ENV: &str = "RUN_STARS_RUNTIME_DIR";
+/// Directory that holds the persistent states, instead of `$XDG_STATE_HOME/run_stars`.
+pub const STATE_DIR_ENV: &str = "RUN_STARS_STATE_DIR";
+/// Separate set of states inside of the state directories.
+pub const NAMESPACE_ENV: &str = "RUN_STARS_NAMESPACE";
+
+#[derive(Clone, PartialEq, Eq, Debug)]
 pub enum Kind {
     Runtime,
     Persistent,
+    Output,
+    Lock,
+}
+
+/// Overrides of the state directories of the effective user, the environment is used for the missing ones.
+#[derive(Default, Debug)]
+pub struct Locations {
+    pub runtime_dir: Option<PathBuf>,
+    pub state_dir:   Option<PathBuf>,
+    pub namespace:   Option<String>,
+}
+
+impl Locations {
+    fn or_env(self) -> Self {
+        let var = |name| std::env::var_os(name).filter(|v| !v.is_empty());
+
+        Locations {
+            runtime_dir: self.runtime_dir.or_else(|| var(RUNTIME_DIR_ENV).map(PathBuf::from)),
+            state_dir:   self.state_dir.or_else(|| var(STATE_DIR_ENV).map(PathBuf::from)),
+            namespace:   self.namespace.or_else(|| var(NAMESPACE_ENV).map(|v| v.to_string_lossy().into_owned())),
+        }
+    }
+}
+
+/// Locations that keep the states of a single user.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Root {
+    /// Name of the user that the states belong to
+    pub owner:     String,
+    /// Set of states that is kept apart from the default one
+    pub namespace: Option<String>,
+    runtime:       PathBuf,
+    persistent:    PathBuf,
+}
+
+static CURRENT: OnceLock<Root> = OnceLock::new();
+
+impl Root {
+    /// Takes the base directories, like `/run` and `/var/lib`.
+    pub fn new(owner: impl Into<String>, runtime: impl AsRef<Path>, persistent: impl AsRef<Path>) -> Self {
+        Root::at(owner, runtime.as_ref().join(DIR_NAME), persistent.as_ref().join(DIR_NAME))
+    }
+
+    /// Takes the directories that hold the states themselves.
+    pub fn at(owner: impl Into<String>, runtime: impl Into<PathBuf>, persistent: impl Into<PathBuf>) -> Self {
+        Root {
+            owner:      owner.into(),
+            namespace:  None,
+            runtime:    runtime.into(),
+            persistent: persistent.into(),
+        }
+    }
+
+    /// Moves the states into a namespace, which is a single directory name, created inside of both locations.
+    pub fn namespace(mut self, namespace: impl Into<String>) -> Result<Self, Error> {
+        let namespace = namespace.into();
+
+        if !is_dir_name(&namespace) {
+            return Err(Error::Namespace(namespace))
+        }
+
+        self.runtime = self.runtime.join(NAMESPACE_DIR_NAME).join(&namespace);
+        self.persistent = self.persistent.join(NAMESPACE_DIR_NAME).join(&namespace);
+        self.namespace = Some(namespace);
+
+        Ok(self)
+    }
+
+    /// Locations of the effective user, that are affected by the environment,
+    /// unless they were set with [`Root::configure`] before.
+    ///
+    /// Panics if the environment has an invalid namespace, see [`Root::try_current`].
+    pub fn current() -> &'static Root {
+        Root::try_current().unwrap_or_else(|e| panic!("{e}, it has to be checked with Root::try_current or Root::configure"))
+    }
+
+    /// Locations of the effective user, like [`Root::current`], or an error if the environment has an invalid namespace.
+    pub fn try_current() -> Result<&'static Root, Error> {
+        if let Some(root) = CURRENT.get() {
+            return Ok(root)
+        }
+
+        let root = Root::locate(Locations::default())?;
+
+        Ok(CURRENT.get_or_init(|| root))
+    }
+
+    /// Sets the locations of the effective user, this has to be done before anything else looks for the states.
+    pub fn configure(locations: Locations) -> Result<&'static Root, Error> {
+        let root = Root::locate(locations)?;
+
+        CURRENT.set(root).map_err(|_| Error::Configured)?;
+
+        Ok(Root::current())
+    }
+
+    fn locate(locations: Locations) -> Result<Root, Error> {
+        let Locations { runtime_dir, state_dir, namespace } = locations.or_env();
+
+        let runtime = runtime_dir.unwrap_or_else(|| Path::new(xdg::runtime()).join(DIR_NAME));
+        let persistent = state_dir.unwrap_or_else(|| Path::new(xdg::state()).join(DIR_NAME));
+
+        let root = Root::at(xdg::user_name(), runtime, persistent);
+
+        match namespace {
+            Some(namespace) => root.namespace(namespace),
+            None => Ok(root),
+        }
+    }
+
+    /// Locations of the system and of every user, whose states can be read,
+    /// along with the ones of the effective user, that always come first.
+    pub fn all() -> Vec<Root> {
+        let current = Root::current();
+        let mut roots = vec![current.clone()];
+
+        let others = xdg::users().into_iter()
+            .map(|user| Root::new(user.name, xdg::runtime_of(user.uid), xdg::state_of(user.uid, &user.home)))
+            .filter_map(|root| match &current.namespace {
+                Some(namespace) => root.namespace(namespace.clone()).ok(),
+                None => Some(root),
+            })
+            .filter(|root| root.runtime != current.runtime && root.persistent != current.persistent)
+            .filter(|root| root.is_readable());
+
+        for root in others {
+            if !roots.iter().any(|known| known.runtime == root.runtime || known.persistent == root.persistent) {
+                roots.push(root);
+            }
+        }
+
+        roots
+    }
+
+    /// Whether there are states that can be listed.
+    fn is_readable(&self) -> bool {
+        std::fs::read_dir(&self.runtime).is_ok() || std::fs::read_dir(&self.persistent).is_ok()
+    }
+
+    #[inline]
+    pub fn is_current(&self) -> bool {
+        self == Root::current()
+    }
+
+    #[inline]
+    pub fn runtime_dir(&self) -> &Path {
+        &self.runtime
+    }
+
+    #[inline]
+    pub fn persistent_dir(&self) -> &Path {
+        &self.persistent
+    }
+
+    #[inline]
+    pub fn control_dir(&self) -> PathBuf {
+        self.runtime.join(CONTROL_DIR_NAME)
+    }
+
+    #[inline]
+    pub fn lock_dir(&self) -> PathBuf {
+        self.runtime.join(LOCK_DIR_NAME)
+    }
+
+    /// Output of the task is stored next to the persistent state, so it's still available after the run is over.
+    ///
+    /// Tasks at the top of the directory keep their names, while the ones of subdirectories
+    /// are named by their path relative to the directory, like the states are, see [`encode`].
+    pub fn log_path(&self, file_name: impl AsRef<OsStr>, dir: impl AsRef<Path>, task: impl AsRef<Path>) -> PathBuf {
+        self.log_dir(file_name).join(log_name(dir.as_ref(), task.as_ref()))
+    }
+
+    #[inline]
+    pub fn log_dir(&self, file_name: impl AsRef<OsStr>) -> PathBuf {
+        self.persistent
+            .join(LOG_DIR_NAME)
+            .join(file_name.as_ref())
+    }
+
+    pub fn history_path(&self, file_name: impl AsRef<OsStr>) -> PathBuf {
+        self.persistent
+            .join(HISTORY_DIR_NAME)
+            .join(file_name.as_ref())
+    }
+
+    #[inline]
+    pub(crate) fn history_dir(&self) -> PathBuf {
+        self.persistent.join(HISTORY_DIR_NAME)
+    }
+
+    #[inline]
+    pub(crate) fn logs_dir(&self) -> PathBuf {
+        self.persistent.join(LOG_DIR_NAME)
+    }
+
+    /// Hashed names can't be decoded, so their paths are kept in a separate file.
+    #[inline]
+    pub fn name_path(&self, file_name: impl AsRef<OsStr>) -> PathBuf {
+        self.persistent
+            .join(NAME_DIR_NAME)
+            .join(file_name.as_ref())
+    }
+
+    /// Path of the directory that a state belongs to, see [`decode`].
+    pub fn path_of(&self, file_name: impl AsRef<OsStr>) -> Option<PathBuf> {
+        let file_name = file_name.as_ref();
+
+        decode(file_name).or_else(|| {
+            let b = std::fs::read(self.name_path(file_name)).ok()?;
+            Some(PathBuf::from(OsString::from_vec(b)))
+        })
+    }
+
+    /// Encodes the path of a directory, keeping the path aside if it had to be hashed.
+    pub fn init_name(&self, dir: impl AsRef<Path>) -> Result<PathBuf, Error> {
+        let dir = dir.as_ref();
+        let file_name = encode(dir);
+
+        if decode(&file_name).is_none() {
+            let p = init_nested(self.init_persistent_dir()?.join(NAME_DIR_NAME))?.join(&file_name);
+
+            crate::write::replace(&p, dir.as_os_str().as_bytes(), false)
+                .map_err(|io| Error::WriteName { path: p, io })?;
+        }
+
+        Ok(file_name)
+    }
+
+    #[inline]
+    pub fn init_runtime_dir(&self) -> Result<PathBuf, Error> {
+        self.init_namespaced(&self.runtime)
+    }
+
+    #[inline]
+    pub fn init_persistent_dir(&self) -> Result<PathBuf, Error> {
+        self.init_namespaced(&self.persistent)
+    }
+
+    /// Namespaces are kept inside of the directory with the default states.
+    fn init_namespaced(&self, state: &Path) -> Result<PathBuf, Error> {
+        if self.namespace.is_none() {
+            return init_dir(state)
+        }
+
+        let default = state.ancestors().nth(2).expect("namespace is always nested twice");
+
+        init_dir(default)?;
+        init_nested(state.to_owned())
+    }
+
+    /// Control sockets live in a directory that is accessible only by the owner,
+    /// so nobody else is able to cancel or start tasks.
+    pub fn init_control_dir(&self) -> Result<PathBuf, Error> {
+        let p = self.init_runtime_dir()?.join(CONTROL_DIR_NAME);
+
+        let Err(io) = DirBuilder::new().mode(0o700).create(&p) else {
+            return Ok(p);
+        };
+
+        match io.kind() {
+            std::io::ErrorKind::AlreadyExists => Ok(p),
+            _ => Err(Error::CreateLocation { path: p, io }),
+        }
+    }
+
+    pub fn init_log_dir(&self, file_name: impl AsRef<OsStr>) -> Result<PathBuf, Error> {
+        init_nested(self.init_persistent_dir()?.join(LOG_DIR_NAME).join(file_name.as_ref()))
+    }
+
+    /// Runner holds a lock on a separate file for as long as it's running,
+    /// because the state itself is replaced on every write.
+    pub fn init_lock_dir(&self) -> Result<PathBuf, Error> {
+        init_nested(self.init_runtime_dir()?.join(LOCK_DIR_NAME))
+    }
+
+    pub fn init_history_dir(&self) -> Result<PathBuf, Error> {
+        init_nested(self.init_persistent_dir()?.join(HISTORY_DIR_NAME))
+    }
+
+    /// Slots of a pool are shared by every runner of the root, so they are kept next to the runtime states.
+    pub fn init_pool_dir(&self, pool: &str) -> Result<PathBuf, Error> {
+        if !is_dir_name(pool) {
+            return Err(Error::Pool(pool.to_owned()))
+        }
+
+        init_nested(self.init_runtime_dir()?.join(POOL_DIR_NAME).join(pool))
+    }
 }
 
 pub fn is_runtime(p: impl AsRef<Path>) -> bool {
@@ -21,115 +322,329 @@ pub fn is_runtime(p: impl AsRef<Path>) -> bool {
 
 #[inline]
 pub(crate) fn runtime_dir() -> PathBuf {
-    PathBuf::from(xdg::runtime()).join(DIR_NAME)
+    Root::current().runtime.clone()
+}
+
+/// Temporary files start with a dot, so they are never mistaken for states, which always start with the version of the encoding.
+#[inline]
+pub fn is_temporary(file_name: impl AsRef<OsStr>) -> bool {
+    file_name.as_ref().as_encoded_bytes().starts_with(b".")
+}
+
+/// Output of the task is stored next to the persistent state, so it's still available after the run is over.
+pub fn log_path(file_name: impl AsRef<OsStr>, dir: impl AsRef<Path>, task: impl AsRef<Path>) -> PathBuf {
+    Root::current().log_path(file_name, dir, task)
 }
 
 #[inline]
-pub(crate) fn persistent_dir() -> PathBuf {
-    PathBuf::from(xdg::state()).join(DIR_NAME)
+pub fn log_dir(file_name: impl AsRef<OsStr>) -> PathBuf {
+    Root::current().log_dir(file_name)
+}
+
+pub fn history_path(file_name: impl AsRef<OsStr>) -> PathBuf {
+    Root::current().history_path(file_name)
 }
 
 #[inline]
 pub fn init_runtime_dir() -> Result<PathBuf, Error> {
-    init_dir(xdg::runtime())
+    Root::current().init_runtime_dir()
 }
 
 #[inline]
 pub fn init_persistent_dir() -> Result<PathBuf, Error> {
-    init_dir(xdg::state())
+    Root::current().init_persistent_dir()
+}
+
+#[inline]
+pub fn init_control_dir() -> Result<PathBuf, Error> {
+    Root::current().init_control_dir()
 }
 
-fn init_dir(p: impl AsRef<Path>) -> Result<PathBuf, Error> {
-    let p = p.as_ref();
+#[inline]
+pub fn init_log_dir(file_name: impl AsRef<OsStr>) -> Result<PathBuf, Error> {
+    Root::current().init_log_dir(file_name)
+}
+
+#[inline]
+pub fn init_lock_dir() -> Result<PathBuf, Error> {
+    Root::current().init_lock_dir()
+}
+
+#[inline]
+pub fn init_history_dir() -> Result<PathBuf, Error> {
+    Root::current().init_history_dir()
+}
+
+fn is_dir_name(s: &str) -> bool {
+    !s.is_empty() && s != "." && s != ".." && !s.contains(['/', '\0'])
+}
+
+fn init_nested(p: PathBuf) -> Result<PathBuf, Error> {
+    std::fs::create_dir_all(&p)
+        .map(|_| p.clone())
+        .map_err(|io| Error::CreateLocation { path: p, io })
+}
+
+/// Creates the directory of the states inside of an existing base directory.
+fn init_dir(state: &Path) -> Result<PathBuf, Error> {
+    let p = state.parent().unwrap_or(state);
 
     match std::fs::metadata(p) {
         Ok(m) if m.is_dir() => {
-            let state = p.join(DIR_NAME);
-
-            let Err(io) = std::fs::create_dir(&state) else {
-                return Ok(state);
+            let Err(io) = std::fs::create_dir(state) else {
+                return Ok(state.to_owned());
             };
 
             match io.kind() {
-                std::io::ErrorKind::AlreadyExists => Ok(state),
-                _ => Err(Error::CreateLocation { path: state.clone(), io}.into()),
+                std::io::ErrorKind::AlreadyExists => Ok(state.to_owned()),
+                _ => Err(Error::CreateLocation { path: state.to_owned(), io }),
             }
         }
-        Ok(_) => Err(Error::NotDirectory(p.to_owned()).into()),
-        Err(io) => Err(Error::AccessLocation { path: p.to_owned(), io }.into()),
+        Ok(_) => Err(Error::NotDirectory(p.to_owned())),
+        Err(io) => Err(Error::AccessLocation { path: p.to_owned(), io }),
     }
 }
 
-const ESCAPE_CHAR: char = '%';
-
-pub fn decode(p: impl Into<OsString>) -> PathBuf {
-    let original = p.into().into_vec();
-    let c = ESCAPE_CHAR as u8;
+/// Path that is safe to show in a terminal, where bytes that aren't valid UTF-8 and control characters are escaped as `\xHH`.
+pub fn printable(p: impl AsRef<Path>) -> String {
+    use std::fmt::Write;
 
-    let b = unsafe {
-        let mut b = UnsafeCell::new(original);
-        let mut chars = memchr_iter(c, &*b.get()).peekable();
+    let mut s = String::new();
 
-        {
-            let b = &mut *b.get_mut();
-            while let Some(i) = chars.next() {
-                if i + 1 != *chars.peek().unwrap_or(&0) {
-                    b[i] = b'/';
-                } else {
-                    chars.next();
-                }
+    for chunk in p.as_ref().as_os_str().as_bytes().utf8_chunks() {
+        for c in chunk.valid().chars() {
+            match c.is_control() {
+                true  => c.encode_utf8(&mut [0; 4]).bytes().for_each(|b| { let _ = write!(s, "\\x{b:02x}"); }),
+                false => s.push(c),
             }
         }
 
-        b.into_inner()
-    };
-
-    let mut chars = memchr_iter(c, b.as_slice()).peekable();
-    if chars.peek().is_some() {
-        let mut offset = 0;
-        let mut b = b.clone();
+        chunk.invalid().iter().for_each(|b| { let _ = write!(s, "\\x{b:02x}"); });
+    }
 
-        while let Some(i) = chars.next() {
-            b.remove(i - offset);
-            offset += 1;
+    s
+}
 
-            chars.next();
+/// Marks the version of the encoding at the start of a file name, names of the legacy encoding start with an escaped slash.
+const ENCODING: &[u8] = b"@1";
+/// Replaces every slash, so that a whole path fits into a single file name.
+const SLASH_CHAR: u8 = b'%';
+/// Starts an escaped byte, that is followed by its two hexadecimal digits.
+const ESCAPE_CHAR: u8 = b'+';
+/// Follows the version in the names that were hashed, because the encoded path was too long.
+const HASH_CHAR: u8 = b'#';
+/// Leaves enough room below NAME_MAX for the temporary files, that add a dot and a process id.
+const MAX_NAME_LEN: usize = 200;
+
+/// Name of the state files of a directory.
+///
+/// Slashes are replaced with `%`, while `%`, `+` and `#` are escaped as `+25`, `+2B` and `+23`, so that every path can be decoded back.
+/// Names that would be too long are replaced with a hash of the path instead,
+/// the path itself is kept aside with [`Root::init_name`].
+pub fn encode(p: impl AsRef<OsStr>) -> PathBuf {
+    let original = p.as_ref().as_bytes();
+    let mut b = ENCODING.to_vec();
+
+    for &c in original {
+        match c {
+            b'/' => b.push(SLASH_CHAR),
+            SLASH_CHAR | ESCAPE_CHAR | HASH_CHAR => b.extend_from_slice(format!("{}{c:02X}", ESCAPE_CHAR as char).as_bytes()),
+            _ => b.push(c),
         }
+    }
 
-        return PathBuf::from(OsString::from_vec(b))
+    if b.len() > MAX_NAME_LEN {
+        b.truncate(ENCODING.len());
+        b.push(HASH_CHAR);
+        b.extend_from_slice(hash(original).as_bytes());
     }
 
     PathBuf::from(OsString::from_vec(b))
 }
 
-pub fn encode(p: impl Into<OsString>) -> PathBuf {
-    let original = p.into().into_vec();
-    let c = ESCAPE_CHAR as u8;
+/// Encoded names always begin with the version, so a plain name of the top level never matches one of them.
+fn log_name(dir: &Path, task: &Path) -> PathBuf {
+    let relative = task.strip_prefix(dir).unwrap_or(task);
+    let mut components = relative.components();
+
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(name)), None) if !name.as_bytes().starts_with(ENCODING) => PathBuf::from(name),
+        _ => encode(relative),
+    }
+}
 
-    let mut chars = memchr_iter(c, original.as_slice()).peekable();
+/// Path of the directory that the state file name was made from,
+/// or nothing if the name is a hash or wasn't made by any version of [`encode`].
+pub fn decode(file_name: impl AsRef<OsStr>) -> Option<PathBuf> {
+    let b = file_name.as_ref().as_bytes();
+
+    if is_legacy(file_name.as_ref()) {
+        return Some(decode_legacy(b))
+    }
+
+    let encoded = b.strip_prefix(ENCODING)?;
+
+    if encoded.first() == Some(&HASH_CHAR) {
+        return None
+    }
 
-    let b = match chars.peek().is_none() {
-        true  => original,
-        false => {
-            let mut offset = 0;
-            let mut b = original.clone();
+    let mut encoded = encoded.iter();
+    let mut decoded = Vec::with_capacity(b.len());
 
-            chars.for_each(|i| { b.insert(i + offset, c); offset += 1 });
+    while let Some(&c) = encoded.next() {
+        match c {
+            SLASH_CHAR => decoded.push(b'/'),
+            ESCAPE_CHAR => {
+                let digits = [*encoded.next()?, *encoded.next()?];
+                let digits = std::str::from_utf8(&digits).ok()?;
 
-            b
+                decoded.push(u8::from_str_radix(digits, 16).ok()?);
+            },
+            _ => decoded.push(c),
+        }
+    }
+
+    Some(PathBuf::from(OsString::from_vec(decoded)))
+}
+
+/// Whether the name was made by the first encoding, which has to be migrated with [`crate::migrate::migrate`].
+#[inline]
+pub fn is_legacy(file_name: impl AsRef<OsStr>) -> bool {
+    file_name.as_ref().as_bytes().first() == Some(&SLASH_CHAR)
+}
+
+/// First encoding replaced slashes with `%` and doubled the `%` itself, which is ambiguous for the paths with `//`,
+/// but those were never written, because the directories were always absolute and normalized.
+fn decode_legacy(b: &[u8]) -> PathBuf {
+    let mut decoded = Vec::with_capacity(b.len());
+    let mut encoded = b.iter().peekable();
+
+    while let Some(&c) = encoded.next() {
+        match c {
+            SLASH_CHAR if encoded.next_if_eq(&&SLASH_CHAR).is_some() => decoded.push(SLASH_CHAR),
+            SLASH_CHAR => decoded.push(b'/'),
+            _ => decoded.push(c),
+        }
+    }
+
+    PathBuf::from(OsString::from_vec(decoded))
+}
+
+/// Name of the state that the legacy encoding would give to a directory.
+pub(crate) fn encode_legacy(p: impl AsRef<OsStr>) -> OsString {
+    let mut encoded = Vec::new();
+
+    for &c in p.as_ref().as_bytes() {
+        match c {
+            b'/' => encoded.push(SLASH_CHAR),
+            SLASH_CHAR => encoded.extend_from_slice(&[SLASH_CHAR, SLASH_CHAR]),
+            _ => encoded.push(c),
+        }
+    }
+
+    OsString::from_vec(encoded)
+}
+
+pub(crate) fn hash(b: &[u8]) -> String {
+    use sha2::{Digest, Sha256};
+    use std::fmt::Write;
+
+    // Half of the digest is more than enough to tell the directories apart
+    Sha256::digest(b)[..16].iter().fold(String::new(), |mut s, b| {
+        let _ = write!(s, "{b:02x}");
+        s
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    /// Paths never contain a null byte, every other one is allowed.
+    fn bytes(max: usize) -> impl Strategy<Value = Vec<u8>> {
+        prop::collection::vec(1u8..=255, 0..max)
+    }
+
+    fn path(b: &[u8]) -> PathBuf {
+        PathBuf::from(OsString::from_vec(b.to_vec()))
+    }
+
+    proptest! {
+        #[test]
+        fn round_trip(b in bytes(400)) {
+            let p = path(&b);
+            let file_name = encode(&p);
+
+            match decode(&file_name) {
+                Some(decoded) => prop_assert_eq!(decoded, p),
+                None => prop_assert!(file_name.as_os_str().as_bytes()[ENCODING.len()] == HASH_CHAR),
+            }
+        }
+
+        #[test]
+        fn short_paths_are_never_hashed(b in bytes(60)) {
+            prop_assert_eq!(decode(encode(path(&b))), Some(path(&b)));
         }
-    };
 
-    unsafe {
-        let mut b = UnsafeCell::new(b);
+        #[test]
+        fn names_fit_into_a_directory(b in bytes(400)) {
+            let file_name = encode(path(&b));
+            let file_name = file_name.as_os_str().as_bytes();
 
-        let chars = memchr_iter(b'/', &*b.get());
+            prop_assert!(file_name.len() <= MAX_NAME_LEN);
+            prop_assert!(!file_name.contains(&b'/'));
+            prop_assert!(!is_temporary(OsStr::from_bytes(file_name)));
+            prop_assert!(!is_legacy(OsStr::from_bytes(file_name)));
+        }
 
-        {
-            let b = &mut *b.get_mut();
-            chars.for_each(|i| b[i] = c);
+        #[test]
+        fn long_paths_are_told_apart(a in bytes(400), b in bytes(400)) {
+            prop_assume!(a != b);
+            prop_assert_ne!(encode(path(&a)), encode(path(&b)));
         }
 
-        PathBuf::from(OsString::from_vec(b.into_inner()))
+        /// Legacy names are ambiguous only when `%` is next to a slash.
+        #[test]
+        fn legacy_round_trip(segments in prop::collection::vec("[^/%]([^/]*[^/%])?", 1..8)) {
+            let p = format!("/{}", segments.join("/"));
+
+            prop_assert!(is_legacy(encode_legacy(&p)));
+            prop_assert_eq!(decode(encode_legacy(&p)), Some(PathBuf::from(p)));
+        }
+    }
+
+    #[test]
+    fn logs_of_subdirectories_are_apart() {
+        let dir = Path::new("/etc/cron.daily");
+        let name = |task: &str| log_name(dir, &dir.join(task));
+
+        assert_eq!(name("backup"), Path::new("backup"));
+        assert_ne!(name("db/backup"), name("web/backup"));
+        assert_ne!(name("db/backup"), name("backup"));
+        assert_ne!(name("@1db%backup"), name("db/backup"));
+    }
+
+    #[test]
+    fn malformed_names() {
+        for file_name in ["@1+2", "@1+G0", "@1+", "@2%tmp", "run_stars", "@1#00ff"] {
+            assert_eq!(decode(file_name), None, "{file_name}");
+        }
+    }
+
+    #[test]
+    fn readable_names() {
+        assert_eq!(encode("/etc/cron.daily"), Path::new("@1%etc%cron.daily"));
+        assert_eq!(encode("/tmp/a%b+c"), Path::new("@1%tmp%a+25b+2Bc"));
+        assert_eq!(decode("%etc%cron.daily%%"), Some(PathBuf::from("/etc/cron.daily%")));
+    }
+
+    #[test]
+    fn locations_in_use_are_kept() {
+        let current = Root::try_current().unwrap();
+
+        assert!(matches!(Root::configure(Locations::default()), Err(Error::Configured)));
+        assert_eq!(Root::current(), current);
     }
 }
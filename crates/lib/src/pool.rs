@@ -0,0 +1,142 @@
+use std::fs::{File, OpenOptions};
+use std::io::ErrorKind;
+use std::num::NonZeroUsize;
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use async_channel::Sender;
+
+use crate::error::{Error, LockError};
+use crate::path::Root;
+
+/// How often the slots are checked again while every one of them is taken.
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Limit on the amount of simultaneously running tasks, that is shared by the runners of different processes.
+///
+/// Every slot is a file in the runtime directory, which is taken by placing a lock on it,
+/// so a slot is released even if its runner is killed. Runners of the same pool should agree on its size,
+/// because every one of them takes only the slots that it knows about.
+#[derive(Clone)]
+pub struct Pool {
+    name:    String,
+    waiters: Sender<Sender<PoolSlot>>,
+}
+
+/// Slot of a pool that is held by a running task, it's released when dropped.
+pub struct PoolSlot {
+    _fd: File,
+}
+
+impl Pool {
+    /// Joins a pool of the root, creating its slots if they don't exist yet.
+    pub fn join(root: &Root, name: &str, size: NonZeroUsize) -> Result<Pool, Error> {
+        let dir = root.init_pool_dir(name)?;
+
+        let slots: Vec<_> = (0..size.get()).map(|i| dir.join(i.to_string())).collect();
+
+        for p in &slots {
+            open(p)?;
+        }
+
+        let (waiters, r_waiters) = async_channel::unbounded::<Sender<PoolSlot>>();
+
+        // Locks can't be awaited, so the waiting tasks are served in turns by a thread that polls the slots
+        std::thread::spawn(move || {
+            while let Ok(waiter) = r_waiters.recv_blocking() {
+                loop {
+                    // Task was cancelled while it was waiting
+                    if waiter.is_closed() {
+                        break
+                    }
+
+                    if let Some(slot) = slots.iter().find_map(|p| try_lock(p)) {
+                        let _ = waiter.try_send(slot);
+                        break
+                    }
+
+                    std::thread::sleep(POLL_INTERVAL);
+                }
+            }
+        });
+
+        Ok(Pool { name: name.to_owned(), waiters })
+    }
+
+    #[inline]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Waits for a free slot, in the order of the calls within the process.
+    pub(crate) async fn acquire(&self) -> Option<PoolSlot> {
+        let (s, r) = async_channel::bounded(1);
+
+        self.waiters.try_send(s).ok()?;
+        r.recv().await.ok()
+    }
+}
+
+fn open(p: &Path) -> Result<File, Error> {
+    OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(p)
+        .map_err(|io| LockError::Set { path: PathBuf::from(p), io }.into())
+}
+
+/// Lock is placed with flock⁽²⁾, because it belongs to the open file instead of the process,
+/// so the runner can hold several slots of the same pool.
+fn try_lock(p: &Path) -> Option<PoolSlot> {
+    let fd = open(p).ok()?;
+
+    loop {
+        if unsafe { libc::flock(fd.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } == 0 {
+            return Some(PoolSlot { _fd: fd })
+        }
+
+        if std::io::Error::last_os_error().kind() != ErrorKind::Interrupted {
+            return None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::pin::pin;
+
+    use futures_lite::future;
+
+    use super::*;
+
+    #[test]
+    fn slots_are_limited() {
+        let dir = std::env::temp_dir().join(format!("run-stars-pool-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let root = Root::at("test", dir.join("run"), dir.join("state"));
+        let pool = Pool::join(&root, "test", NonZeroUsize::new(2).unwrap()).unwrap();
+
+        // Runner of another process, that has joined the same pool
+        let other = Pool::join(&root, "test", NonZeroUsize::new(2).unwrap()).unwrap();
+
+        let first = future::block_on(pool.acquire()).unwrap();
+        let second = future::block_on(other.acquire()).unwrap();
+
+        let mut third = pin!(pool.acquire());
+        assert!(future::block_on(future::poll_once(&mut third)).is_none());
+
+        // Both slots are checked a few times while the third one is waiting
+        std::thread::sleep(POLL_INTERVAL * 2);
+        assert!(future::block_on(future::poll_once(&mut third)).is_none());
+
+        drop(first);
+
+        let third = future::block_on(third).unwrap();
+
+        drop((second, third));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,142 @@
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+
+use async_channel::{Receiver, Sender};
+use rustix::process::{Pid, Signal};
+
+use crate::StateChange;
+
+/// Bookkeeping of a single run that is shared between tasks and control requests.
+///
+/// Queue stays open while there are tasks that are waiting or running,
+/// so that finished tasks can be queued once more.
+pub struct Queue {
+    queue:   Sender<usize>,
+    states:  Sender<(usize, StateChange)>,
+    pending: Cell<usize>,
+    /// Tasks that are queued only once everything before them is finished
+    batches: RefCell<VecDeque<Vec<usize>>>,
+
+    children:  RefCell<Vec<Option<Pid>>>,
+    cancelled: RefCell<Vec<bool>>,
+    /// Tasks that are waiting for a slot, they are woken up by the cancellation
+    waiting:   RefCell<Vec<Option<Sender<()>>>>,
+}
+
+impl Queue {
+    pub fn new(len: usize) -> (Self, Receiver<usize>, Receiver<(usize, StateChange)>) {
+        let (queue, r_queue) = async_channel::unbounded();
+        let (states, r_states) = async_channel::unbounded();
+
+        let queue = Queue {
+            queue,
+            states,
+            // Guard that keeps the queue open until all initial tasks are pushed, see Queue::seal
+            pending:   Cell::new(1),
+            batches:   RefCell::new(VecDeque::new()),
+            children:  RefCell::new(vec![None; len]),
+            cancelled: RefCell::new(vec![false; len]),
+            waiting:   RefCell::new(vec![None; len]),
+        };
+
+        (queue, r_queue, r_states)
+    }
+
+    pub fn push(&self, i: usize) -> bool {
+        self.cancelled.borrow_mut()[i] = false;
+        self.send(i)
+    }
+
+    /// Queues a batch after the previous one, or right away if there is none, before the queue is sealed.
+    pub fn push_batch(&self, batch: Vec<usize>) {
+        let mut batches = self.batches.borrow_mut();
+
+        match batches.is_empty() && self.pending.get() == 1 {
+            true  => batch.into_iter().for_each(|i| { self.send(i); }),
+            false => batches.push_back(batch),
+        }
+    }
+
+    /// Tasks of a batch that were cancelled while waiting for their turn stay cancelled.
+    fn send(&self, i: usize) -> bool {
+        match self.queue.try_send(i) {
+            Ok(_) => {
+                self.pending.set(self.pending.get() + 1);
+                true
+            },
+            Err(_) => false,
+        }
+    }
+
+    #[inline]
+    pub fn seal(&self) {
+        self.done();
+    }
+
+    pub fn done(&self) {
+        let pending = self.pending.get() - 1;
+        self.pending.set(pending);
+
+        if pending == 0 {
+            // Next batch takes over the guard, so that it's queued once the previous one is finished
+            let batch = self.batches.borrow_mut().pop_front();
+
+            if let Some(batch) = batch {
+                self.pending.set(1);
+                batch.into_iter().for_each(|i| { self.send(i); });
+
+                return self.done()
+            }
+
+            self.queue.close();
+            self.states.close();
+        }
+    }
+
+    pub fn report(&self, i: usize, state: StateChange) {
+        let _ = self.states.try_send((i, state));
+    }
+
+    pub fn spawned(&self, i: usize, pid: u32) {
+        self.children.borrow_mut()[i] = Pid::from_raw(pid as i32);
+    }
+
+    pub fn exited(&self, i: usize) {
+        self.children.borrow_mut()[i] = None;
+    }
+
+    pub fn is_cancelled(&self, i: usize) -> bool {
+        self.cancelled.borrow()[i]
+    }
+
+    /// Resolves once the task is cancelled, or right away if it already was.
+    pub fn cancellation(&self, i: usize) -> impl std::future::Future<Output = ()> {
+        let (s, r) = async_channel::bounded(1);
+
+        match self.is_cancelled(i) {
+            true  => { s.close(); },
+            false => self.waiting.borrow_mut()[i] = Some(s),
+        }
+
+        async move { let _ = r.recv().await; }
+    }
+
+    /// Terminates a task with all of its children if it's running,
+    /// waiting tasks will be skipped when their turn comes.
+    pub fn cancel(&self, i: usize) {
+        self.cancelled.borrow_mut()[i] = true;
+
+        if let Some(waiting) = self.waiting.borrow_mut()[i].take() {
+            waiting.close();
+        }
+
+        if let Some(pid) = self.children.borrow()[i] {
+            let _ = rustix::process::kill_process_group(pid, Signal::Term);
+        }
+    }
+
+    pub fn kill(&self) {
+        let len = self.cancelled.borrow().len();
+        (0..len).for_each(|i| self.cancel(i));
+    }
+}
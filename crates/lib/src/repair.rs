@@ -0,0 +1,98 @@
+use std::io::ErrorKind;
+use std::path::Path;
+
+use jiff::Timestamp;
+
+use crate::error::Error;
+use crate::write::RunLock;
+use crate::{Reader, State, Status};
+
+/// Message of the tasks that were still running or waiting when their runner has stopped.
+pub const INTERRUPTED: &str = "interrupted";
+
+/// Moves a runtime state that was left behind by a runner, which has stopped without reporting the end,
+/// into the persistent storage and the history, marking the tasks that didn't finish as interrupted.
+///
+/// Returns the amount of interrupted tasks, or nothing if the state is not dangling.
+pub fn repair(state: &State) -> Result<Option<usize>, Error> {
+    // Runner that is still alive holds the lock, it's kept until the state is moved,
+    // so that another runner of the same directory waits for the repair to finish
+    let Some(_lock) = RunLock::try_acquire(&state.root, &state.file_name)? else {
+        return Ok(None)
+    };
+
+    let runtime_path = state.root.runtime_dir().join(&state.file_name);
+
+    let meta = match std::fs::metadata(&runtime_path) {
+        Ok(meta) => meta,
+        Err(io) if io.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(io) => return Err(Error::Metadata { path: runtime_path, io }),
+    };
+
+    let mut reader = Reader::new(&runtime_path);
+    let mut tasks = reader.read()?;
+    let mut interrupted = 0;
+
+    // Last write of the state is the last moment when the runner was known to be alive
+    let end = meta.modified()
+        .ok()
+        .and_then(|time| Timestamp::try_from(time).ok());
+
+    for task in tasks.iter_mut().filter(|task| matches!(task.status, Status::Running | Status::Waiting)) {
+        // Modification time is coarse, so it might be a bit earlier than the start
+        if task.status == Status::Running {
+            task.end = end.map(|end| task.start.map_or(end, |start| end.max(start)));
+        }
+
+        task.status = Status::Unknown;
+        task.message = String::from(INTERRUPTED);
+
+        interrupted += 1;
+    }
+
+    let mut buffer = Vec::new();
+    let _ = crate::write::write(std::io::sink(), &mut buffer, &tasks, reader.order());
+
+    let persistent_path = state.root.init_persistent_dir()?.join(&state.file_name);
+
+    crate::write::replace(&persistent_path, &buffer, true)
+        .map_err(|io| Error::WriteState { path: persistent_path, io })?;
+
+    crate::write::append_history(&state.root, &state.file_name, &buffer)?;
+
+    remove_file(&runtime_path)?;
+    remove_file(crate::control::socket_path(&state.root, &state.file_name))?;
+
+    Ok(Some(interrupted))
+}
+
+/// Removes everything that is known about a state: runtime and persistent states, history, logs, a control socket and a kept path.
+///
+/// Returns false if the state is in use by a runner.
+pub fn remove(state: &State) -> Result<bool, Error> {
+    let Some(_lock) = RunLock::try_acquire(&state.root, &state.file_name)? else {
+        return Ok(false)
+    };
+
+    remove_file(state.root.runtime_dir().join(&state.file_name))?;
+    remove_file(state.root.persistent_dir().join(&state.file_name))?;
+    remove_file(state.root.history_path(&state.file_name))?;
+    remove_file(crate::control::socket_path(&state.root, &state.file_name))?;
+    remove_file(state.root.name_path(&state.file_name))?;
+
+    let logs = state.root.log_dir(&state.file_name);
+
+    match std::fs::remove_dir_all(&logs) {
+        Err(io) if io.kind() != ErrorKind::NotFound => Err(Error::Remove { path: logs, io }),
+        _ => Ok(true),
+    }
+}
+
+fn remove_file(p: impl AsRef<Path>) -> Result<(), Error> {
+    let p = p.as_ref();
+
+    match std::fs::remove_file(p) {
+        Err(io) if io.kind() != ErrorKind::NotFound => Err(Error::Remove { path: p.to_owned(), io }),
+        _ => Ok(()),
+    }
+}
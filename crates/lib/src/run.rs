@@ -0,0 +1,1040 @@
+use std::cell::RefCell;
+use std::cmp::{Ordering, Reverse};
+use std::collections::{HashMap, HashSet};
+use std::fs::DirEntry;
+use std::future::Future;
+use std::num::NonZeroUsize;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::process::{Command, Stdio};
+use std::rc::Rc;
+use std::str::FromStr;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use async_channel::{Receiver, Sender};
+use futures_concurrency::future::Join;
+use futures_lite::future;
+use futures_concurrency::prelude::ConcurrentStream;
+use futures_concurrency::stream::StreamExt;
+
+use crate::error::{ControlError, Error, ParseError};
+use crate::budget::Budget;
+use crate::manifest::{Manifest, MANIFEST_NAME};
+use crate::path::Root;
+use crate::pool::Pool;
+use crate::queue::Queue;
+use crate::write::{Journal, RunLock, StateFile};
+use crate::{migrate, output, path, signal_name, wait, Order, StateChange, Status, Task};
+
+/// Smallest amount of changes in the journal after which the runtime state is written in full again.
+const JOURNAL_LIMIT: usize = 64;
+
+/// Change of a single task, that is reported after it was written to the runtime state.
+#[derive(Clone, Debug)]
+pub struct Event {
+    /// Position of the task in the state
+    pub task:   usize,
+    pub path:   PathBuf,
+    pub change: StateChange,
+}
+
+/// Stream of changes, that ends with the run.
+pub type Events = Receiver<Event>;
+
+type ChangeHook = Box<dyn FnMut(&Event)>;
+type SpawnHook = Box<dyn FnMut(&mut Command)>;
+type ErrorHook = Box<dyn FnMut(Error)>;
+
+/// What is done with the symbolic links that are found in the directory.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Symlinks {
+    /// Links are ignored
+    #[default]
+    Skip,
+    /// Links to files are executed, links to directories are ignored
+    Files,
+    /// Links to files are executed and links to directories are descended into, every directory only once
+    Follow,
+}
+
+impl FromStr for Symlinks {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "skip"   => Ok(Symlinks::Skip),
+            "files"  => Ok(Symlinks::Files),
+            "follow" => Ok(Symlinks::Follow),
+            _ => Err(ParseError::Symlinks(s.to_owned())),
+        }
+    }
+}
+
+/// Order in which the files of subdirectories are executed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Traversal {
+    /// Files of a subdirectory are executed in place of it, along with the files of its parent
+    #[default]
+    DepthFirst,
+    /// Files of every level are executed only after all files of the previous level are finished
+    Levels,
+}
+
+impl FromStr for Traversal {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "depth-first" => Ok(Traversal::DepthFirst),
+            "levels"      => Ok(Traversal::Levels),
+            _ => Err(ParseError::Traversal(s.to_owned())),
+        }
+    }
+}
+
+/// Limit on the amount of simultaneously running tasks, that is shared between the runs of several directories.
+///
+/// Task takes as many slots as it weighs, see [`Manifest`].
+#[derive(Clone)]
+pub struct Slots(Rc<Budget>);
+
+impl Slots {
+    pub fn new(limit: NonZeroUsize) -> Self {
+        Slots(Budget::new(limit.get()))
+    }
+}
+
+#[derive(Default)]
+struct Hooks {
+    change: Vec<ChangeHook>,
+    spawn:  Option<SpawnHook>,
+    error:  Option<ErrorHook>,
+}
+
+/// Executes files of a directory and keeps the same state files as the `run-stars` binary,
+/// so that a run that was started from another program can be followed with the usual tools.
+///
+/// ```no_run
+/// use run_stars_lib::run::Runner;
+///
+/// let (execution, events) = Runner::new("/etc/cron.daily")
+///     .on_change(|event| println!("{} {}", event.path.display(), event.change.status.name()))
+///     .start()?;
+///
+/// let tasks = futures_lite::future::block_on(execution)?;
+/// # Ok::<(), run_stars_lib::error::Error>(())
+/// ```
+pub struct Runner {
+    dir:       PathBuf,
+    tasks:     Option<Vec<PathBuf>>,
+    depth:     usize,
+    symlinks:  Symlinks,
+    traversal: Traversal,
+    order:     Order,
+    limit:     Option<NonZeroUsize>,
+    slots:     Option<Slots>,
+    pool:      Option<Pool>,
+    reverse:   bool,
+    wait:      bool,
+    journal:   bool,
+    logs:      bool,
+    hooks:     Hooks,
+}
+
+impl Runner {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Runner {
+            dir:       dir.into(),
+            tasks:     None,
+            depth:     0,
+            symlinks:  Symlinks::default(),
+            traversal: Traversal::default(),
+            order:     Order::default(),
+            limit:     None,
+            slots:     None,
+            pool:      None,
+            reverse:   false,
+            wait:      true,
+            journal:   false,
+            logs:      true,
+            hooks:     Hooks::default(),
+        }
+    }
+
+    /// Executes only these files in the given order, instead of every file of the directory by name.
+    ///
+    /// Relative paths are relative to the directory.
+    pub fn tasks<P: Into<PathBuf>>(mut self, tasks: impl IntoIterator<Item = P>) -> Self {
+        self.tasks = Some(tasks.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Executes the files of subdirectories up to this depth, only the top level is executed by default.
+    pub fn depth(mut self, depth: usize) -> Self {
+        self.depth = depth;
+        self
+    }
+
+    /// Decides what is done with the symbolic links, they are skipped by default.
+    pub fn symlinks(mut self, symlinks: Symlinks) -> Self {
+        self.symlinks = symlinks;
+        self
+    }
+
+    /// Decides when the files of subdirectories are executed, see [`Traversal`].
+    pub fn traversal(mut self, traversal: Traversal) -> Self {
+        self.traversal = traversal;
+        self
+    }
+
+    /// Decides the order of the files, which is recorded in the state, they are ordered by name by default.
+    pub fn order(mut self, order: Order) -> Self {
+        self.order = order;
+        self
+    }
+
+    /// Limits the amount of simultaneously running tasks.
+    pub fn limit(mut self, limit: Option<NonZeroUsize>) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    /// Shares the limit of simultaneously running tasks with the other runs that were given the same slots.
+    pub fn slots(mut self, slots: Slots) -> Self {
+        self.slots = Some(slots);
+        self
+    }
+
+    /// Shares the limit of simultaneously running tasks with the runners of other processes that have joined the same pool.
+    pub fn pool(mut self, pool: Pool) -> Self {
+        self.pool = Some(pool);
+        self
+    }
+
+    /// Executes the tasks in the reverse order.
+    pub fn reverse(mut self, reverse: bool) -> Self {
+        self.reverse = reverse;
+        self
+    }
+
+    /// Waits for another run of the same directory to finish, instead of failing with [`Error::Busy`], enabled by default.
+    pub fn wait(mut self, wait: bool) -> Self {
+        self.wait = wait;
+        self
+    }
+
+    /// Appends changes of tasks to the runtime state instead of rewriting it every time.
+    pub fn journal(mut self, journal: bool) -> Self {
+        self.journal = journal;
+        self
+    }
+
+    /// Keeps the output of every task in a log, enabled by default.
+    pub fn logs(mut self, logs: bool) -> Self {
+        self.logs = logs;
+        self
+    }
+
+    /// Calls a function for every change of a task, after it was written to the runtime state.
+    pub fn on_change(mut self, f: impl FnMut(&Event) + 'static) -> Self {
+        self.hooks.change.push(Box::new(f));
+        self
+    }
+
+    /// Adjusts a command of a task before it's spawned.
+    pub fn on_spawn(mut self, f: impl FnMut(&mut Command) + 'static) -> Self {
+        self.hooks.spawn = Some(Box::new(f));
+        self
+    }
+
+    /// Receives the problems that don't stop the run, like a state or a log that couldn't be written.
+    pub fn on_error(mut self, f: impl FnMut(Error) + 'static) -> Self {
+        self.hooks.error = Some(Box::new(f));
+        self
+    }
+
+    /// Absolute path to the directory.
+    pub fn dir(&self) -> Result<PathBuf, Error> {
+        std::path::absolute(&self.dir)
+            .map_err(|io| Error::Absolute { path: self.dir.clone(), io })
+    }
+
+    /// Files that will be executed, in the order of their execution.
+    pub fn plan(&self) -> Result<Vec<PathBuf>, Error> {
+        let mut tasks = self.list()?;
+
+        if self.reverse {
+            tasks.reverse();
+        }
+
+        Ok(tasks)
+    }
+
+    /// Files in the order of the state.
+    fn list(&self) -> Result<Vec<PathBuf>, Error> {
+        let dir = self.dir()?;
+
+        if let Some(tasks) = &self.tasks {
+            return Ok(tasks.iter().map(|p| dir.join(p)).collect())
+        }
+
+        let mut tasks = Vec::new();
+        let mut visited = HashSet::new();
+
+        if let Ok(meta) = std::fs::metadata(&dir) {
+            visited.insert((meta.dev(), meta.ino()));
+        }
+
+        match self.traversal {
+            Traversal::DepthFirst => self.walk(&dir, 0, &mut visited, &mut tasks)?,
+            Traversal::Levels => {
+                let mut level = vec![dir.clone()];
+
+                for depth in 0..=self.depth {
+                    let mut next = Vec::new();
+
+                    for dir in level {
+                        let (files, dirs) = self.read_level(&dir, depth, &mut visited)?;
+
+                        tasks.extend(files);
+                        next.extend(dirs);
+                    }
+
+                    if next.is_empty() {
+                        break
+                    }
+
+                    level = next;
+                }
+            },
+        }
+
+        self.arrange(&dir, &mut tasks)?;
+
+        Ok(tasks)
+    }
+
+    /// Orders the files by something else than their names.
+    fn arrange(&self, dir: &Path, tasks: &mut [PathBuf]) -> Result<(), Error> {
+        match self.order {
+            Order::Name | Order::Natural => {},
+            Order::Manifest => {
+                let manifest = Manifest::read(dir)?.unwrap_or_default();
+
+                let keys = tasks.iter()
+                    .map(|p| Reverse(manifest.priority(p.strip_prefix(dir).unwrap_or(p))))
+                    .collect();
+
+                self.sort(dir, tasks, keys);
+            },
+            // Tasks that have never finished might be the longest ones
+            Order::Duration => {
+                let durations = crate::parse_runs(Root::current().history_path(path::encode(dir)))
+                    .map(durations)
+                    .unwrap_or_default();
+
+                let keys = tasks.iter()
+                    .map(|p| Reverse(durations.get(p).copied().unwrap_or(Duration::MAX)))
+                    .collect();
+
+                self.sort(dir, tasks, keys);
+            },
+            Order::Shuffle => {
+                let mut seed = std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .map_or(0, |d| d.as_nanos() as u64) ^ u64::from(std::process::id()) << 32;
+
+                let keys = tasks.iter().map(|_| splitmix(&mut seed)).collect();
+
+                self.sort(dir, tasks, keys);
+            },
+        }
+
+        Ok(())
+    }
+
+    /// Stable sort by the keys of the tasks, that keeps the levels apart if they are executed in batches.
+    fn sort<K: Ord>(&self, dir: &Path, tasks: &mut [PathBuf], keys: Vec<K>) {
+        let level = |i: usize| match self.traversal {
+            Traversal::DepthFirst => 0,
+            Traversal::Levels => level(dir, &tasks[i]),
+        };
+
+        let mut order: Vec<_> = (0..tasks.len()).collect();
+        order.sort_by(|&a, &b| (level(a), &keys[a]).cmp(&(level(b), &keys[b])));
+
+        let arranged: Vec<_> = order.into_iter().map(|i| tasks[i].clone()).collect();
+        tasks.clone_from_slice(&arranged);
+    }
+
+    fn walk(&self, dir: &Path, depth: usize, visited: &mut HashSet<(u64, u64)>, tasks: &mut Vec<PathBuf>) -> Result<(), Error> {
+        for (p, is_dir) in self.read(dir, depth, visited)? {
+            match is_dir {
+                true  => self.walk(&p, depth + 1, visited, tasks)?,
+                false => tasks.push(p),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn read_level(&self, dir: &Path, depth: usize, visited: &mut HashSet<(u64, u64)>) -> Result<(Vec<PathBuf>, Vec<PathBuf>), Error> {
+        let (dirs, files) = self.read(dir, depth, visited)?
+            .into_iter()
+            .partition::<Vec<_>, _>(|(_, is_dir)| *is_dir);
+
+        Ok((files.into_iter().map(|(p, _)| p).collect(), dirs.into_iter().map(|(p, _)| p).collect()))
+    }
+
+    /// Files and the subdirectories that have to be descended into, sorted by name.
+    fn read(&self, dir: &Path, depth: usize, visited: &mut HashSet<(u64, u64)>) -> Result<Vec<(PathBuf, bool)>, Error> {
+        let mut entries: Vec<_> = std::fs::read_dir(dir)
+            .map_err(|io| Error::AccessLocation { path: dir.to_owned(), io })?
+            .flatten()
+            // Manifest is read by the runner instead
+            .filter(|entry| depth != 0 || entry.file_name() != MANIFEST_NAME)
+            .filter_map(|entry| {
+                let is_dir = self.is_dir(&entry, depth, visited)?;
+                Some((entry.path(), is_dir))
+            })
+            .collect();
+
+        match self.order {
+            Order::Natural => entries.sort_by(|(a, _), (b, _)| natural(a.as_os_str().as_bytes(), b.as_os_str().as_bytes())),
+            _ => entries.sort(),
+        }
+
+        Ok(entries)
+    }
+
+    /// Tells if an entry is a directory to descend into, or a file to execute, or neither.
+    fn is_dir(&self, entry: &DirEntry, depth: usize, visited: &mut HashSet<(u64, u64)>) -> Option<bool> {
+        let mut file_type = entry.file_type().ok()?;
+
+        if file_type.is_symlink() {
+            if self.symlinks == Symlinks::Skip {
+                return None
+            }
+
+            file_type = std::fs::metadata(entry.path()).ok()?.file_type();
+
+            if file_type.is_dir() && self.symlinks != Symlinks::Follow {
+                return None
+            }
+        }
+
+        if file_type.is_dir() && depth < self.depth {
+            // Followed links might lead into a directory that was already seen, even into a parent
+            let meta = std::fs::metadata(entry.path()).ok()?;
+            return visited.insert((meta.dev(), meta.ino())).then_some(true)
+        }
+
+        file_type.is_file().then_some(false)
+    }
+
+    /// Waits for another run of the same directory to finish and prepares the run,
+    /// tasks are started once the execution is polled.
+    pub fn start(mut self) -> Result<(Execution, Events), Error> {
+        let dir = self.dir()?;
+        let tasks: Vec<_> = self.list()?.into_iter().map(Task::new).collect();
+
+        let root = Root::current();
+
+        // Previous runs of the directory might be kept where an older version has put them, or named by it
+        if let Err(e) = migrate::migrate_location(root).and_then(|_| migrate::migrate_dir(root, &dir)) {
+            if let Some(f) = self.hooks.error.as_mut() {
+                f(e)
+            }
+        }
+
+        let file_name = root.init_name(&dir)?;
+        let runtime_path = path::init_runtime_dir()?.join(&file_name);
+
+        // Another run of the same directory has to finish first
+        let lock = match self.wait {
+            true  => RunLock::acquire(root, &file_name)?,
+            false => RunLock::try_acquire(root, &file_name)?.ok_or_else(|| Error::Busy(dir.clone()))?,
+        };
+
+        let manifest = Manifest::read(&dir)?.unwrap_or_default();
+
+        let weights = tasks.iter()
+            .map(|task| manifest.task(task.path.strip_prefix(&dir).unwrap_or(&task.path)))
+            .collect();
+
+        let classes = manifest.classes.iter()
+            .map(|class| Budget::new(class.limit.get()))
+            .collect();
+
+        let (queue, r_queue, states) = Queue::new(tasks.len());
+
+        let mut order: Vec<_> = (0..tasks.len()).collect();
+
+        if self.reverse {
+            order.reverse();
+        }
+
+        match self.traversal {
+            Traversal::DepthFirst => queue.push_batch(order),
+            // Level of a task is the amount of directories between it and the directory of the run
+            Traversal::Levels => {
+                let level = |i: usize| level(&dir, &tasks[i].path);
+
+                for batch in order.chunk_by(|a, b| level(*a) == level(*b)) {
+                    queue.push_batch(batch.to_vec());
+                }
+            },
+        }
+
+        queue.seal();
+
+        let control = Control {
+            inner: Rc::new(Inner { queue, dir, file_name, tasks: RefCell::new(tasks) }),
+        };
+
+        let (s_events, events) = async_channel::unbounded();
+
+        let run = Run {
+            control:  control.clone(),
+            hooks:    RefCell::new(self.hooks),
+            events:   s_events,
+            limit:    self.limit.map(Slots::new),
+            slots:    self.slots,
+            // Files that were given explicitly are kept in their order
+            order:    self.tasks.is_none().then_some(self.order),
+            classes,
+            weights,
+            pool:     self.pool,
+            logs:     self.logs,
+            journal:  self.journal,
+            runtime_path,
+            lock,
+        };
+
+        let execution = Execution {
+            control,
+            future: Box::pin(run.execute(r_queue, states)),
+        };
+
+        Ok((execution, events))
+    }
+}
+
+/// Run that has started, it resolves to the final state of the tasks once all of them are finished.
+pub struct Execution {
+    control: Control,
+    future:  Pin<Box<dyn Future<Output = Result<Vec<Task>, Error>>>>,
+}
+
+impl Execution {
+    #[inline]
+    pub fn control(&self) -> Control {
+        self.control.clone()
+    }
+}
+
+impl Future for Execution {
+    type Output = Result<Vec<Task>, Error>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.future.as_mut().poll(cx)
+    }
+}
+
+/// Handle of a run that can be used while it's executed.
+#[derive(Clone)]
+pub struct Control {
+    inner: Rc<Inner>,
+}
+
+struct Inner {
+    queue:     Queue,
+    dir:       PathBuf,
+    file_name: PathBuf,
+    tasks:     RefCell<Vec<Task>>,
+}
+
+impl Control {
+    /// Absolute path to the directory.
+    #[inline]
+    pub fn dir(&self) -> &Path {
+        &self.inner.dir
+    }
+
+    /// Name of the state files, see [`path::encode`].
+    #[inline]
+    pub fn file_name(&self) -> &Path {
+        &self.inner.file_name
+    }
+
+    /// Current state of the tasks.
+    pub fn tasks(&self) -> Vec<Task> {
+        self.inner.tasks.borrow().clone()
+    }
+
+    fn status(&self, i: usize) -> Result<Status, ControlError> {
+        self.inner.tasks.borrow().get(i)
+            .map(|task| task.status)
+            .ok_or(ControlError::NoTask(i))
+    }
+
+    /// Terminates a task with all of its children if it's running,
+    /// a waiting task will be skipped when its turn comes.
+    pub fn cancel(&self, i: usize) -> Result<(), ControlError> {
+        match self.status(i)? {
+            Status::Running | Status::Waiting => {
+                self.inner.queue.cancel(i);
+                Ok(())
+            },
+            _ => Err(ControlError::NotRunning),
+        }
+    }
+
+    /// Queues a finished task once more.
+    pub fn rerun(&self, i: usize) -> Result<(), ControlError> {
+        match self.status(i)? {
+            Status::Running | Status::Waiting => Err(ControlError::AlreadyRunning),
+            _ => match self.inner.queue.push(i) {
+                true => {
+                    self.inner.queue.report(i, StateChange::new(Status::Waiting));
+                    Ok(())
+                },
+                false => Err(ControlError::Finished),
+            },
+        }
+    }
+
+    /// Cancels every task.
+    pub fn kill(&self) {
+        self.inner.queue.kill();
+    }
+}
+
+struct Run {
+    control:      Control,
+    hooks:        RefCell<Hooks>,
+    events:       Sender<Event>,
+    limit:        Option<Slots>,
+    slots:        Option<Slots>,
+    order:        Option<Order>,
+    /// Budget of every class of the manifest
+    classes:      Vec<Rc<Budget>>,
+    /// Class and weight of every task
+    weights:      Vec<(Option<usize>, NonZeroUsize)>,
+    pool:         Option<Pool>,
+    logs:         bool,
+    journal:      bool,
+    runtime_path: PathBuf,
+    lock:         RunLock,
+}
+
+impl Run {
+    fn error(&self, e: Error) {
+        if let Some(f) = self.hooks.borrow_mut().error.as_mut() {
+            f(e)
+        }
+    }
+
+    async fn execute(self, queue: Receiver<usize>, states: Receiver<(usize, StateChange)>) -> Result<Vec<Task>, Error> {
+        let inner = &self.control.inner;
+
+        let logs = self.logs && path::init_log_dir(&inner.file_name)
+            .map_err(|e| self.error(e))
+            .is_ok();
+
+        let wait_for_processes = queue.co().for_each(|i| self.task(i, logs));
+
+        let (_, buffer) = (wait_for_processes, self.write_states(states)).join().await;
+
+        let persistent_path = path::init_persistent_dir()?.join(&inner.file_name);
+
+        crate::write::replace(&persistent_path, &buffer, true)
+            .map_err(|io| Error::WriteState { path: persistent_path, io })?;
+
+        if let Err(e) = crate::write::append_history(Root::current(), &inner.file_name, &buffer) {
+            self.error(e);
+        }
+
+        let _ = std::fs::remove_file(&self.runtime_path);
+
+        let tasks = inner.tasks.borrow().clone();
+
+        drop(self.lock);
+
+        Ok(tasks)
+    }
+
+    async fn task(&self, i: usize, logs: bool) {
+        let queue = &self.control.inner.queue;
+        let p = self.control.inner.tasks.borrow()[i].path.clone();
+
+        let (class, weight) = self.weights[i];
+
+        // Class is taken first, so that a task which waits for its class doesn't hold back the others
+        let slots = async {
+            let mut grants = Vec::new();
+
+            let budgets = class.map(|class| &self.classes[class])
+                .into_iter()
+                .chain(self.limit.iter().chain(&self.slots).map(|slots| &slots.0));
+
+            for budget in budgets {
+                grants.push(budget.take(weight.get()).await);
+            }
+
+            let pool_slot = match &self.pool {
+                Some(pool) => pool.acquire().await,
+                None => None,
+            };
+
+            Some((grants, pool_slot))
+        };
+
+        // Task stays waiting until the slots are released by other runs, unless it's cancelled
+        let _slots = future::or(slots, async {
+            queue.cancellation(i).await;
+            None
+        }).await;
+
+        if queue.is_cancelled(i) {
+            queue.report(i, StateChange::new(Status::Cancelled).message("cancelled before start"));
+            queue.done();
+
+            return
+        }
+
+        let mut command = Command::new(&p);
+
+        if let Some(f) = self.hooks.borrow_mut().spawn.as_mut() {
+            f(&mut command);
+        }
+
+        // Own process group lets cancellation reach everything that task has spawned
+        command.process_group(0);
+
+        let log = match logs {
+            true  => output::log(&self.control.inner.file_name, &self.control.inner.dir, &p).map_err(|e| self.error(e)).ok(),
+            false => None,
+        };
+
+        let c = command
+            .stdout(output::stdout(log.as_ref()))
+            .stderr(Stdio::piped())
+            .spawn();
+
+        let change = match &c {
+            Ok(_)  => StateChange::new(Status::Running),
+            Err(e) => StateChange::new(Status::Failure).code(1).message(e.kind().to_string()),
+        };
+
+        queue.report(i, change);
+
+        let mut child = match c {
+            Ok(child) => child,
+            Err(io) => {
+                self.error(Error::Spawn { path: p, io });
+                return queue.done()
+            },
+        };
+
+        queue.spawned(i, child.id());
+
+        let stderr = output::forward(child.stderr.take(), log);
+
+        let exit = wait::wait(child).await;
+
+        queue.exited(i);
+
+        let change = match exit {
+            Ok((exit, usage)) => {
+                let (status, code) = match exit.success() {
+                    _ if queue.is_cancelled(i) => (Status::Cancelled, 0),
+                    true  => (Status::Success, 0),
+                    false => (Status::Failure, exit.code().unwrap_or(1)),
+                };
+
+                let signal = exit.signal().map(|signal| signal as u8);
+
+                let last_line = stderr.last_line().await;
+
+                StateChange::new(status)
+                    .code(code as u8)
+                    .signal(signal)
+                    .usage(usage)
+                    .message(message(status, exit.code(), signal, last_line))
+            },
+            Err(e) => {
+                let change = match queue.is_cancelled(i) {
+                    true  => StateChange::new(Status::Cancelled).message(e.to_string()),
+                    false => StateChange::new(Status::Failure).code(1).message(e.to_string()),
+                };
+
+                self.error(Error::Wait { path: p, io: e });
+
+                change
+            },
+        };
+
+        queue.report(i, change);
+        queue.done();
+    }
+
+    /// Applies the changes to the tasks and writes them to the runtime state, returns the final state.
+    async fn write_states(&self, states: Receiver<(usize, StateChange)>) -> Vec<u8> {
+        let inner = &self.control.inner;
+
+        let runtime = StateFile::File(self.runtime_path.clone());
+        let mut journal = self.journal.then(|| Journal::new(&self.runtime_path));
+
+        let mut buffer = vec![];
+        let mut changed = vec![];
+        let mut events = vec![];
+
+        // Tasks might wait for the slots of other runs, so the run is visible before any of them has started
+        let initial = match journal.as_mut() {
+            Some(journal) => journal.compact(&mut buffer, &inner.tasks.borrow(), self.order),
+            None => crate::write::write(std::io::sink(), &mut buffer, &inner.tasks.borrow(), self.order).and_then(|_| runtime.replace(&buffer)),
+        };
+
+        if let Err(io) = initial {
+            self.error(Error::WriteState { path: self.runtime_path.clone(), io });
+        }
+
+        while let Ok(mut msg) = states.recv().await {
+            let mut tasks = inner.tasks.borrow_mut();
+
+            loop {
+                let (i, change) = msg;
+
+                let t = &mut tasks[i];
+                t.apply(&change);
+
+                if !changed.contains(&i) {
+                    changed.push(i);
+                }
+
+                events.push(Event { task: i, path: t.path.clone(), change });
+
+                msg = match states.try_recv() {
+                    Ok(msg) => msg,
+                    Err(_) => break,
+                }
+            }
+
+            let result = match journal.as_mut() {
+                // Journal is compacted once it outgrows the state itself
+                Some(journal) if !journal.is_started() || journal.len() >= tasks.len().max(JOURNAL_LIMIT) => {
+                    journal.compact(&mut buffer, &tasks, self.order)
+                },
+                Some(journal) => changed.iter().try_for_each(|&i| journal.append(&mut buffer, i, &tasks[i])),
+                None => crate::write::write(std::io::sink(), &mut buffer, &tasks, self.order).and_then(|_| runtime.replace(&buffer)),
+            };
+
+            changed.clear();
+
+            // Hooks are free to use the control once the tasks are released
+            drop(tasks);
+
+            if let Err(io) = result {
+                self.error(Error::WriteState { path: self.runtime_path.clone(), io });
+            }
+
+            for event in events.drain(..) {
+                self.hooks.borrow_mut().change.iter_mut().for_each(|f| f(&event));
+                let _ = self.events.try_send(event);
+            }
+        }
+
+        self.events.close();
+
+        // Final state is always written in full
+        let _ = crate::write::write(std::io::sink(), &mut buffer, &inner.tasks.borrow(), self.order);
+
+        buffer
+    }
+}
+
+/// Level of a task is the amount of directories between it and the directory of the run.
+fn level(dir: &Path, p: &Path) -> usize {
+    p.strip_prefix(dir).map_or(0, |p| p.components().count())
+}
+
+/// Compares the runs of digits by their value and everything else byte by byte.
+fn natural(mut a: &[u8], mut b: &[u8]) -> Ordering {
+    fn digits(s: &[u8]) -> (&[u8], &[u8]) {
+        let n = s.iter().take_while(|c| c.is_ascii_digit()).count();
+        s.split_at(n)
+    }
+
+    fn value(n: &[u8]) -> &[u8] {
+        let zeros = n.iter().take_while(|&&c| c == b'0').count();
+        &n[zeros..]
+    }
+
+    loop {
+        match (a.first(), b.first()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let ((n, rest_a), (m, rest_b)) = (digits(a), digits(b));
+                let (value_n, value_m) = (value(n), value(m));
+
+                // Numbers of the same value are told apart by their leading zeros
+                let ordering = value_n.len().cmp(&value_m.len())
+                    .then_with(|| value_n.cmp(value_m))
+                    .then_with(|| n.len().cmp(&m.len()));
+
+                if ordering.is_ne() {
+                    return ordering
+                }
+
+                (a, b) = (rest_a, rest_b);
+            },
+            (Some(x), Some(y)) if x != y => return x.cmp(y),
+            _ => (a, b) = (&a[1..], &b[1..]),
+        }
+    }
+}
+
+/// Average duration of every task that has finished in the runs.
+fn durations(runs: Vec<Vec<Task>>) -> HashMap<PathBuf, Duration> {
+    let mut total: HashMap<PathBuf, (Duration, u32)> = HashMap::new();
+
+    for task in runs.into_iter().flatten().filter(|task| task.end.is_some()) {
+        if let Some(d) = task.duration() {
+            let (sum, n) = total.entry(task.path).or_default();
+            *sum += d;
+            *n += 1;
+        }
+    }
+
+    total.into_iter()
+        .map(|(p, (sum, n))| (p, sum / n))
+        .collect()
+}
+
+/// Next number of the splitmix64 sequence, which is good enough to shuffle the tasks.
+fn splitmix(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9E3779B97F4A7C15);
+
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+
+    z ^ (z >> 31)
+}
+
+/// Signal explains the most, then the last complaint of the task and only then a bare exit code.
+fn message(status: Status, code: Option<i32>, signal: Option<u8>, last_line: String) -> String {
+    match (signal, code) {
+        (Some(signal), _) => match signal_name(signal) {
+            Some(name) => format!("killed by {name}"),
+            None => format!("killed by signal {signal}"),
+        },
+        _ if !last_line.is_empty() => last_line,
+        (_, Some(code)) if status == Status::Failure => format!("exit code {code}"),
+        _ => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn natural_order() {
+        let mut names = ["10-bar", "2-foo", "02-foo", "a", "1", "b10", "b9", "b09x"];
+        names.sort_by(|a, b| natural(a.as_bytes(), b.as_bytes()));
+
+        assert_eq!(names, ["1", "2-foo", "02-foo", "10-bar", "a", "b9", "b09x", "b10"]);
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("run-stars-{name}-{}", std::process::id()));
+
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        dir
+    }
+
+    #[test]
+    fn manifest_order() {
+        let dir = temp_dir("manifest-order");
+
+        for name in ["a", "b", "c", "d"] {
+            std::fs::write(dir.join(name), "").unwrap();
+        }
+
+        std::fs::write(dir.join(MANIFEST_NAME), "b priority=5\nc priority=-9223372036854775808\nd priority=9223372036854775807\n").unwrap();
+
+        let tasks = Runner::new(&dir).order(Order::Manifest).plan();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(tasks.unwrap(), ["d", "b", "a", "c"].map(|name| dir.join(name)));
+    }
+
+    #[test]
+    fn duration_order() {
+        let dir = Path::new("/dir");
+
+        let task = |name: &str, secs: Option<i64>| {
+            let mut task = Task::new(dir.join(name));
+            let start = jiff::Timestamp::UNIX_EPOCH;
+
+            task.start = Some(start);
+            task.end = secs.map(|secs| start + jiff::SignedDuration::from_secs(secs));
+            task
+        };
+
+        // Average of b is longer than the one of a, c has never finished and d is new
+        let runs = vec![
+            vec![task("a", Some(4)), task("b", Some(1)), task("c", None)],
+            vec![task("a", Some(2)), task("b", Some(9))],
+        ];
+
+        let durations = durations(runs);
+
+        assert_eq!(durations[&dir.join("a")], Duration::from_secs(3));
+        assert_eq!(durations[&dir.join("b")], Duration::from_secs(5));
+        assert!(!durations.contains_key(&dir.join("c")));
+
+        let mut tasks = ["a", "b", "c", "d"].map(|name| dir.join(name));
+        let keys = tasks.iter().map(|p| Reverse(durations.get(p).copied().unwrap_or(Duration::MAX))).collect();
+
+        Runner::new(dir).sort(dir, &mut tasks, keys);
+
+        assert_eq!(tasks, ["c", "d", "b", "a"].map(|name| dir.join(name)));
+    }
+
+    #[test]
+    fn shuffle_keeps_levels() {
+        let dir = Path::new("/dir");
+        let runner = Runner::new(dir).traversal(Traversal::Levels);
+
+        let planned: Vec<_> = (0..20)
+            .map(|i| dir.join(format!("{}/{i}", "sub/".repeat(i % 3))))
+            .collect();
+
+        let mut seed = 0;
+        let mut tasks = planned.clone();
+        let keys = tasks.iter().map(|_| splitmix(&mut seed)).collect();
+
+        runner.sort(dir, &mut tasks, keys);
+
+        let mut sorted = tasks.clone();
+        sorted.sort();
+
+        let mut expected = planned.clone();
+        expected.sort();
+
+        assert_eq!(sorted, expected);
+        assert_ne!(tasks, planned);
+        assert!(tasks.is_sorted_by_key(|p| level(dir, p)));
+    }
+}
@@ -0,0 +1,45 @@
+use std::io;
+use std::os::unix::process::ExitStatusExt;
+use std::process::{Child, ExitStatus};
+use std::time::Duration;
+
+use crate::Usage;
+
+/// Reaps a child on its own thread with wait4⁽²⁾, because an exit status alone doesn't carry the resource usage.
+pub async fn wait(child: Child) -> io::Result<(ExitStatus, Usage)> {
+    let (s, r) = async_channel::bounded(1);
+    let pid = child.id() as libc::pid_t;
+
+    std::thread::spawn(move || {
+        let _ = s.send_blocking(wait4(pid));
+    });
+
+    r.recv().await.unwrap_or_else(|_| Err(io::ErrorKind::Other.into()))
+}
+
+fn wait4(pid: libc::pid_t) -> io::Result<(ExitStatus, Usage)> {
+    let mut status = 0;
+
+    // SAFETY: rusage is a plain C struct, that is fully written by wait4
+    let mut rusage: libc::rusage = unsafe { std::mem::zeroed() };
+
+    while unsafe { libc::wait4(pid, &mut status, 0, &mut rusage) } == -1 {
+        let e = io::Error::last_os_error();
+
+        if e.kind() != io::ErrorKind::Interrupted {
+            return Err(e)
+        }
+    }
+
+    let usage = Usage {
+        user:    duration(rusage.ru_utime),
+        system:  duration(rusage.ru_stime),
+        max_rss: rusage.ru_maxrss as u64,
+    };
+
+    Ok((ExitStatus::from_raw(status), usage))
+}
+
+fn duration(t: libc::timeval) -> Duration {
+    Duration::new(t.tv_sec as u64, t.tv_usec as u32 * 1000)
+}
@@ -1,71 +1,346 @@
-use std::{fs::File, io::Write, os::{fd::AsRawFd, unix::fs::FileExt}};
+use std::ffi::{OsStr, OsString};
+use std::fmt::Display;
+use std::fs::{File, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::os::fd::AsRawFd;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
 
-use crate::{Task, SPLIT_CHAR};
+use crate::error::{Error, LockError};
+use crate::path::Root;
 
-pub fn write(mut w: impl Write, buffer: &mut Vec<u8>, tasks: &[Task]) -> Result<(), std::io::Error> {
+use crate::{Column, Order, Task, COLUMNS, COLUMNS_KEY, HEADER_CHAR, JOURNAL_CHAR, ORDER_KEY, QUOTE_CHAR, SPLIT_CHAR};
+
+pub fn write(mut w: impl Write, buffer: &mut Vec<u8>, tasks: &[Task], order: Option<Order>) -> Result<(), std::io::Error> {
     buffer.clear();
 
+    write!(buffer, "{HEADER_CHAR}{COLUMNS_KEY} ")?;
+
+    for (i, column) in COLUMNS.iter().enumerate() {
+        write_separated(buffer, i, column.name())?;
+    }
+
+    writeln!(buffer)?;
+
+    if let Some(order) = order {
+        writeln!(buffer, "{HEADER_CHAR}{ORDER_KEY} {}", order.name())?;
+    }
+
     for task in tasks.iter() {
-        write!(buffer, "{}{SPLIT_CHAR}{}{SPLIT_CHAR}{}{SPLIT_CHAR}{}\n",
-            task.status,
-            task.code,
-            task.time,
-            task.path.to_string_lossy())?;
+        write_task(buffer, task)?;
     }
 
-    w.write(&buffer)?;
+    w.write_all(buffer)?;
     w.flush()
 }
 
-pub enum StateFile {
-    File(File),
-    Sink,
+fn write_task(buffer: &mut Vec<u8>, task: &Task) -> Result<(), std::io::Error> {
+    for (i, column) in COLUMNS.iter().enumerate() {
+        write_field(buffer, i, task, *column)?;
+    }
+
+    writeln!(buffer)
 }
 
-impl StateFile {
-    pub fn lock(&self) -> Result<(), std::io::Error> {
-        let Self::File(fd) = self else {
-            return Ok(())
-        };
+fn write_field(buffer: &mut Vec<u8>, i: usize, task: &Task, column: Column) -> Result<(), std::io::Error> {
+    let usage = task.usage.as_ref();
 
-        let mut lock = libc::flock {
-            l_type:   libc::F_WRLCK  as _,
-            l_whence: libc::SEEK_END as _,
-            l_start:  0,
-            l_len:    0,
-            l_pid:    0,
-        };
+    match column {
+        Column::Status   => write_separated(buffer, i, task.status),
+        Column::Code     => write_separated(buffer, i, task.code),
+        Column::Time     => write_separated(buffer, i, task.time),
+        Column::Signal   => write_separated(buffer, i, Optional(task.signal)),
+        Column::Attempts => write_separated(buffer, i, task.attempts),
+        Column::Start    => write_separated(buffer, i, Optional(task.start)),
+        Column::End      => write_separated(buffer, i, Optional(task.end)),
+        Column::User     => write_separated(buffer, i, Optional(usage.map(|u| Seconds(u.user.as_secs_f64())))),
+        Column::System   => write_separated(buffer, i, Optional(usage.map(|u| Seconds(u.system.as_secs_f64())))),
+        Column::Rss      => write_separated(buffer, i, Optional(usage.map(|u| u.max_rss))),
+        Column::Message  => write_separated(buffer, i, Quoted(Sanitized(&task.message))),
+        Column::Path     => write_separated(buffer, i, Quoted(task.path.to_string_lossy())),
+        Column::EscapedPath => write_separated(buffer, i, Quoted(Escaped(&task.path))),
+    }
+}
+
+fn write_separated(buffer: &mut Vec<u8>, i: usize, value: impl Display) -> Result<(), std::io::Error> {
+    match i {
+        0 => write!(buffer, "{value}"),
+        _ => write!(buffer, "{SPLIT_CHAR}{value}"),
+    }
+}
+
+/// Missing values are written as empty fields.
+struct Optional<T>(Option<T>);
 
-        match unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_SETLKW, std::ptr::from_mut(&mut lock)) } {
-            -1 => Err(std::io::Error::last_os_error()),
-            _  => Ok(()),
+impl<T: Display> Display for Optional<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.0 {
+            Some(value) => value.fmt(f),
+            None => Ok(()),
         }
     }
 }
 
-impl Write for StateFile {
-    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-        match self {
-            StateFile::File(fd) => {
-                fd.set_len(0)?;
-                fd.write_all_at(buf, 0).map(|_| buf.len())
-            },
-            StateFile::Sink => Ok(buf.len()),
+/// Field that contains a separator or a quote is enclosed in quotes, where quotes are doubled, as in RFC 4180.
+struct Quoted<T>(T);
+
+impl<T: Display> Display for Quoted<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = self.0.to_string();
+
+        match s.contains([SPLIT_CHAR, QUOTE_CHAR]) {
+            true  => write!(f, "{QUOTE_CHAR}{}{QUOTE_CHAR}", s.replace(QUOTE_CHAR, "\"\"")),
+            false => f.write_str(&s),
         }
     }
+}
+
+/// Every task takes a single line, so line breaks are replaced.
+struct Sanitized<'a>(&'a str);
+
+impl Display for Sanitized<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use std::fmt::Write;
+
+        self.0.chars().try_for_each(|c| match c {
+            '\n' | '\r' => f.write_char(' '),
+            c           => f.write_char(c),
+        })
+    }
+}
+
+/// Path fits into a single line and is read back byte by byte: backslashes are doubled,
+/// while control characters and bytes that are not a part of valid UTF-8 are written as `\xHH`.
+struct Escaped<'a>(&'a Path);
+
+impl Display for Escaped<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use std::fmt::Write;
+
+        for chunk in self.0.as_os_str().as_bytes().utf8_chunks() {
+            for c in chunk.valid().chars() {
+                match c {
+                    '\\' => f.write_str("\\\\")?,
+                    c if c.is_control() => c.encode_utf8(&mut [0; 4]).bytes().try_for_each(|b| write!(f, "\\x{b:02x}"))?,
+                    c => f.write_char(c)?,
+                }
+            }
+
+            chunk.invalid().iter().try_for_each(|b| write!(f, "\\x{b:02x}"))?;
+        }
+
+        Ok(())
+    }
+}
+
+struct Seconds(f64);
+
+impl Display for Seconds {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:.6}", self.0)
+    }
+}
+
+/// Appends a final state to the history of previous runs, keeping only a limited amount of them.
+pub fn append_history(root: &Root, file_name: impl AsRef<OsStr>, b: &[u8]) -> Result<(), Error> {
+    let p = root.init_history_dir()?.join(file_name.as_ref());
+
+    let mut runs = match p.is_file() {
+        true  => crate::split_runs(&p)?,
+        false => Vec::new(),
+    };
+
+    runs.push(b.to_vec());
+
+    let skip = runs.len().saturating_sub(crate::HISTORY_LIMIT);
+
+    replace(&p, &runs[skip..].concat(), true)
+        .map_err(|io| Error::WriteHistory { path: p, io })
+}
+
+/// Replaces a file at once by renaming a temporary file over it, so that readers never see it half written.
+///
+/// Synchronization is only needed for the files that have to survive a crash.
+pub fn replace(p: impl AsRef<Path>, b: &[u8], sync: bool) -> std::io::Result<()> {
+    let p = p.as_ref();
+
+    let mut temp = OsString::from(".");
+    temp.push(p.file_name().unwrap_or_default());
+    temp.push(format!(".{}", std::process::id()));
+
+    let temp = p.with_file_name(temp);
+
+    let result = File::create(&temp)
+        .and_then(|mut f| {
+            f.write_all(b)?;
+
+            match sync {
+                true  => f.sync_all(),
+                false => Ok(()),
+            }
+        })
+        .and_then(|_| std::fs::rename(&temp, p));
+
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temp);
+    }
 
-    fn flush(&mut self) -> std::io::Result<()> {
+    result
+}
+
+pub enum StateFile {
+    File(PathBuf),
+    Sink,
+}
+
+impl StateFile {
+    /// Replaces the whole state at once, so readers never see it half written.
+    pub fn replace(&self, buffer: &[u8]) -> Result<(), std::io::Error> {
         match self {
-            StateFile::File(fd) => fd.flush(),
+            StateFile::File(p) => replace(p, buffer, false),
             StateFile::Sink => Ok(()),
         }
     }
 }
 
-impl Drop for StateFile {
-    fn drop(&mut self) {
-        if let StateFile::File(fd) = self {
-            let _ = fd.sync_all();
+/// State that is written in full only once in a while and is appended with the changes of single tasks in between.
+///
+/// Each change is numbered, so that readers can tell if something went missing,
+/// while a partially appended line is just not finished yet.
+pub struct Journal {
+    path:    PathBuf,
+    fd:      Option<File>,
+    seq:     u64,
+    entries: usize,
+}
+
+impl Journal {
+    pub fn new(p: impl Into<PathBuf>) -> Self {
+        Journal {
+            path:    p.into(),
+            fd:      None,
+            seq:     0,
+            entries: 0,
         }
     }
+
+    /// Amount of changes that were appended since the state was written in full.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.entries
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.entries == 0
+    }
+
+    /// Whether the state was written in full at least once.
+    #[inline]
+    pub fn is_started(&self) -> bool {
+        self.fd.is_some()
+    }
+
+    /// Replaces the state with all of the tasks, which starts the journal over.
+    pub fn compact(&mut self, buffer: &mut Vec<u8>, tasks: &[Task], order: Option<Order>) -> Result<(), std::io::Error> {
+        self.fd = None;
+
+        write(std::io::sink(), buffer, tasks, order)?;
+        replace(&self.path, buffer, false)?;
+
+        self.fd = Some(OpenOptions::new().append(true).open(&self.path)?);
+        self.entries = 0;
+
+        Ok(())
+    }
+
+    /// Appends a new version of a task on the position `i`, the state has to be written in full first.
+    pub fn append(&mut self, buffer: &mut Vec<u8>, i: usize, task: &Task) -> Result<(), std::io::Error> {
+        let Some(fd) = self.fd.as_mut() else {
+            return Err(std::io::Error::new(ErrorKind::NotFound, "state has to be written in full before the journal"))
+        };
+
+        self.seq += 1;
+
+        buffer.clear();
+        write!(buffer, "{JOURNAL_CHAR}{}{SPLIT_CHAR}{i}{SPLIT_CHAR}", self.seq)?;
+        write_task(buffer, task)?;
+
+        // Whole line is appended with a single write
+        fd.write_all(buffer)?;
+        self.entries += 1;
+
+        Ok(())
+    }
+}
+
+/// Write lock that marks a run as active for as long as it's held.
+///
+/// Lock file is removed when the lock is released, so after the lock is placed,
+/// it has to be checked that the file wasn't removed or replaced in the meantime.
+pub struct RunLock {
+    _fd:  File,
+    path: PathBuf,
+}
+
+impl RunLock {
+    /// Waits until another runner of the same directory is finished.
+    pub fn acquire(root: &Root, file_name: impl AsRef<OsStr>) -> Result<RunLock, Error> {
+        Self::place(root, file_name, libc::F_SETLKW).map(|lock| lock.expect("blocking lock is always placed"))
+    }
+
+    /// Returns nothing if the lock is held by somebody else.
+    pub fn try_acquire(root: &Root, file_name: impl AsRef<OsStr>) -> Result<Option<RunLock>, Error> {
+        Self::place(root, file_name, libc::F_SETLK)
+    }
+
+    fn place(root: &Root, file_name: impl AsRef<OsStr>, command: libc::c_int) -> Result<Option<RunLock>, Error> {
+        let p = root.init_lock_dir()?.join(file_name.as_ref());
+
+        loop {
+            let fd = OpenOptions::new()
+                .create(true)
+                .truncate(false)
+                .write(true)
+                .open(&p)
+                .map_err(|io| LockError::Set { path: p.clone(), io })?;
+
+            let mut lock = libc::flock {
+                l_type:   libc::F_WRLCK as _,
+                l_whence: 0,
+                l_start:  0,
+                l_len:    0,
+                l_pid:    0,
+            };
+
+            if unsafe { libc::fcntl(fd.as_raw_fd(), command, std::ptr::from_mut(&mut lock)) } == -1 {
+                let io = std::io::Error::last_os_error();
+
+                return match io.kind() {
+                    ErrorKind::WouldBlock | ErrorKind::PermissionDenied => Ok(None),
+                    ErrorKind::Interrupted => continue,
+                    _ => Err(LockError::Set { path: p, io }.into()),
+                }
+            }
+
+            let placed = fd.metadata().map_err(|io| LockError::Set { path: p.clone(), io })?;
+
+            match std::fs::metadata(&p) {
+                Ok(current) if current.ino() == placed.ino() && current.dev() == placed.dev() => {
+                    return Ok(Some(RunLock { _fd: fd, path: p }))
+                },
+                Ok(_) => continue,
+                Err(io) if io.kind() == ErrorKind::NotFound => continue,
+                Err(io) => return Err(LockError::Set { path: p, io }.into()),
+            }
+        }
+    }
+}
+
+impl Drop for RunLock {
+    fn drop(&mut self) {
+        // File is removed while the lock is still held, the lock itself is released with the descriptor
+        let _ = std::fs::remove_file(&self.path);
+    }
 }
@@ -1,5 +1,52 @@
 use std::{fs::File, io::BufRead, sync::OnceLock};
 
+/// Account from /etc/passwd.
+#[derive(Clone, Debug)]
+pub struct User {
+    pub uid:  u32,
+    pub name: String,
+    pub home: String,
+}
+
+/// Every account that can be parsed from /etc/passwd.
+pub fn users() -> Vec<User> {
+    let Ok(passwd) = File::open("/etc/passwd") else {
+        return Vec::new()
+    };
+
+    std::io::BufReader::new(passwd).lines()
+        .map_while(Result::ok)
+        .filter_map(|l| {
+            let mut parts = l.split(':');
+
+            let name = parts.next()?.to_owned();
+            let uid = parts.nth(1)?.parse().ok()?;
+            let home = parts.nth(2)?.to_owned();
+
+            Some(User { uid, name, home })
+        })
+        .collect()
+}
+
+fn current_user() -> Option<&'static User> {
+    static USER: OnceLock<Option<User>> = OnceLock::new();
+
+    USER.get_or_init(|| {
+        let uid = rustix::process::geteuid().as_raw();
+        users().into_iter().find(|user| user.uid == uid)
+    }).as_ref()
+}
+
+/// Name of the effective user, or its id if it has no account.
+pub fn user_name() -> &'static str {
+    static NAME: OnceLock<String> = OnceLock::new();
+
+    NAME.get_or_init(|| match current_user() {
+        Some(user) => user.name.clone(),
+        None => rustix::process::geteuid().as_raw().to_string(),
+    })
+}
+
 pub fn home() -> &'static str {
     static HOME: OnceLock<String> = OnceLock::new();
 
@@ -8,19 +55,9 @@ pub fn home() -> &'static str {
             return s
         }
 
-        let uid = Some(rustix::process::geteuid().as_raw().to_string());
-        let passwd = File::open("/etc/passwd").expect("/etc/passwd should be accessable if $HOME is not set");
-
-        for l in std::io::BufReader::new(passwd).lines() {
-            let l = l.expect("reading /etc/passwd should not cause issues");
-            let mut parts = l.split(':');
-
-            if uid.as_deref() == parts.nth(2) {
-                return parts.nth(2).map(str::to_owned).expect("/etc/passwd should not be malformed");
-            }
-        }
-
-        unreachable!()
+        current_user()
+            .map(|user| user.home.clone())
+            .expect("/etc/passwd should have an account of the effective user if $HOME is not set")
     }).as_ref()
 }
 
@@ -32,13 +69,7 @@ pub fn runtime() -> &'static str {
             return s
         }
 
-        let uid = rustix::process::geteuid();
-
-        if uid.is_root() {
-            return "/run".to_string()
-        }
-
-        format!("/run/user/{}", uid.as_raw())
+        runtime_of(rustix::process::geteuid().as_raw())
     })
 }
 
@@ -50,12 +81,34 @@ pub fn state() -> &'static str {
             return s
         }
 
-        let uid = rustix::process::geteuid();
+        state_of(rustix::process::geteuid().as_raw(), home())
+    }).as_ref()
+}
+
+/// State directory of the effective user that older versions have used by mistake, the home joined with `.local/state`
+/// without a slash, unless it's the same as the [`state`].
+pub fn legacy_state() -> Option<String> {
+    if std::env::var_os("XDG_STATE_HOME").is_some() || rustix::process::geteuid().is_root() {
+        return None
+    }
 
-        if uid.is_root() {
-            return "/var/lib".to_string();
-        }
+    let legacy = format!("{}.local/state", home());
 
-        format!("{}{}", home(), ".local/state")
-    }).as_ref()
+    (legacy != state()).then_some(legacy)
+}
+
+/// Default runtime directory of a user, that doesn't take the environment into account.
+pub fn runtime_of(uid: u32) -> String {
+    match uid {
+        0 => "/run".to_string(),
+        _ => format!("/run/user/{uid}"),
+    }
+}
+
+/// Default state directory of a user, that doesn't take the environment into account.
+pub fn state_of(uid: u32, home: &str) -> String {
+    match uid {
+        0 => "/var/lib".to_string(),
+        _ => format!("{}/.local/state", home.trim_end_matches('/')),
+    }
 }
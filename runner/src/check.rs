@@ -0,0 +1,272 @@
+use std::fmt::Write as _;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use jiff::Timestamp;
+
+use run_stars_lib::{State, Status, Task};
+use run_stars_lib::error::Error;
+
+#[derive(argh::FromArgs)]
+#[argh(subcommand, name = "check")]
+/// Check the health of the runs as a Nagios or Icinga plugin.
+pub struct Args {
+    /// check only this directory
+    #[argh(positional)]
+    dir: Option<PathBuf>,
+
+    /// amount of failed tasks that is a warning
+    #[argh(option)]
+    failed_warning: Option<usize>,
+
+    /// amount of failed tasks that is critical, 1 by default
+    #[argh(option, default = "1")]
+    failed_critical: usize,
+
+    /// time since the last run of a directory that is a warning, like 90m, 25h or 2d
+    #[argh(option, from_str_fn(parse_age))]
+    stale_warning: Option<Duration>,
+
+    /// time since the last run of a directory that is critical
+    #[argh(option, from_str_fn(parse_age))]
+    stale_critical: Option<Duration>,
+
+    /// amount of interrupted runs, whose runtime state is left without a lock, that is a warning, 1 by default
+    #[argh(option, default = "1")]
+    dangling_warning: usize,
+
+    /// amount of interrupted runs that is critical
+    #[argh(option)]
+    dangling_critical: Option<usize>,
+}
+
+/// Plugin states, their values are the exit codes.
+#[derive(Clone, Copy, PartialEq)]
+enum Level {
+    Ok       = 0,
+    Warning  = 1,
+    Critical = 2,
+    Unknown  = 3,
+}
+
+impl Level {
+    fn name(self) -> &'static str {
+        match self {
+            Level::Ok       => "OK",
+            Level::Warning  => "WARNING",
+            Level::Critical => "CRITICAL",
+            Level::Unknown  => "UNKNOWN",
+        }
+    }
+
+    /// Known problem outweighs the inability to read something.
+    fn rank(self) -> u8 {
+        match self {
+            Level::Ok       => 0,
+            Level::Unknown  => 1,
+            Level::Warning  => 2,
+            Level::Critical => 3,
+        }
+    }
+
+    fn max(self, other: Level) -> Level {
+        match self.rank() < other.rank() {
+            true  => other,
+            false => self,
+        }
+    }
+
+    fn of(value: u64, warning: Option<u64>, critical: Option<u64>) -> Level {
+        match value {
+            v if critical.is_some_and(|c| v >= c) => Level::Critical,
+            v if warning.is_some_and(|w| v >= w)  => Level::Warning,
+            _ => Level::Ok,
+        }
+    }
+}
+
+struct Run {
+    path:  PathBuf,
+    state: State,
+    tasks: Vec<Task>,
+    /// Seconds since the last change of a task, that is zero while the run is active
+    age:   Option<u64>,
+}
+
+/// Returns the exit code of the plugin, that is also used for the failures of the check itself.
+pub fn run(args: Args) -> i32 {
+    let now = Timestamp::now();
+
+    let dir = args.dir
+        .map(|dir| std::path::absolute(&dir).unwrap_or(dir));
+
+    let states = match run_stars_lib::states() {
+        Ok(states) => states,
+        Err(e) => {
+            println!("RUN-STARS UNKNOWN - {}", first_line(&e.to_string()));
+            return Level::Unknown as i32
+        },
+    };
+
+    let mut unreadable = 0;
+    let mut details = String::new();
+
+    let mut runs: Vec<_> = states.into_iter()
+        .filter(|state| dir.as_ref().is_none_or(|dir| state.path() == *dir))
+        .map(|state| {
+            let tasks = match state.tasks() {
+                Ok((tasks, _)) => tasks,
+                Err(errors) => {
+                    unreadable += 1;
+
+                    errors.iter()
+                        .filter(|e| !matches!(e, Error::NotFound(_)))
+                        .for_each(|e| { let _ = writeln!(details, "{}", first_line(&e.to_string())); });
+
+                    Vec::new()
+                },
+            };
+
+            let age = match state.running {
+                true  => Some(0),
+                false => tasks.iter()
+                    .map(|task| task.end.unwrap_or(task.time))
+                    .max()
+                    .map(|last| (now.as_second() - last.as_second()).max(0) as u64),
+            };
+
+            Run { path: state.path(), state, tasks, age }
+        })
+        .collect();
+
+    runs.sort_by(|a, b| a.path.cmp(&b.path));
+
+    if runs.is_empty() {
+        let what = match &dir {
+            Some(dir) => format!("no state was found for {}", dir.to_string_lossy()),
+            None => String::from("no state was found"),
+        };
+
+        println!("RUN-STARS UNKNOWN - {what}");
+        return Level::Unknown as i32
+    }
+
+    let failed: Vec<_> = runs.iter()
+        .flat_map(|run| run.tasks.iter())
+        .filter(|task| task.status == Status::Failure)
+        .collect();
+
+    let dangling: Vec<_> = runs.iter()
+        .filter(|run| run.state.runtime && !run.state.running)
+        .collect();
+
+    let stale_warning = args.stale_warning.map(|d| d.as_secs());
+    let stale_critical = args.stale_critical.map(|d| d.as_secs());
+
+    let stale: Vec<_> = runs.iter()
+        .filter_map(|run| Some((run, run.age?)))
+        .filter(|&(_, age)| Level::of(age, stale_warning, stale_critical) != Level::Ok)
+        .collect();
+
+    let failed_level = Level::of(failed.len() as u64, args.failed_warning.map(|n| n as u64), Some(args.failed_critical as u64));
+    let dangling_level = Level::of(dangling.len() as u64, Some(args.dangling_warning as u64), args.dangling_critical.map(|n| n as u64));
+
+    let unreadable_level = match unreadable {
+        0 => Level::Ok,
+        _ => Level::Unknown,
+    };
+
+    let level = stale.iter()
+        .map(|&(_, age)| Level::of(age, stale_warning, stale_critical))
+        .fold(unreadable_level.max(failed_level).max(dangling_level), Level::max);
+
+    let mut summary = Vec::new();
+
+    if !failed.is_empty() {
+        summary.push(plural(failed.len(), "failed task", "failed tasks"));
+    }
+
+    if !dangling.is_empty() {
+        summary.push(plural(dangling.len(), "interrupted run", "interrupted runs"));
+    }
+
+    if !stale.is_empty() {
+        summary.push(plural(stale.len(), "stale run", "stale runs"));
+    }
+
+    if unreadable > 0 {
+        summary.push(plural(unreadable, "unreadable state", "unreadable states"));
+    }
+
+    if summary.is_empty() {
+        let tasks = runs.iter().map(|run| run.tasks.len()).sum();
+        summary.push(format!("{} with {}", plural(runs.len(), "run", "runs"), plural(tasks, "task", "tasks")));
+    }
+
+    failed.iter().for_each(|task| {
+        let _ = match task.message.is_empty() {
+            true  => writeln!(details, "{} has failed", task.path.to_string_lossy()),
+            false => writeln!(details, "{} has failed: {}", task.path.to_string_lossy(), task.message),
+        };
+    });
+
+    dangling.iter().for_each(|run| {
+        let _ = writeln!(details, "{} was interrupted", run.path.to_string_lossy());
+    });
+
+    stale.iter().for_each(|(run, age)| {
+        let _ = writeln!(details, "{} last ran {} ago", run.path.to_string_lossy(), format_age(*age));
+    });
+
+    let threshold = |n: Option<u64>| n.map(|n| n.to_string()).unwrap_or_default();
+    let age = runs.iter().filter_map(|run| run.age).max().unwrap_or_default();
+
+    println!("RUN-STARS {} - {} | failed={};{};{};0 dangling={};{};{};0 age={age}s;{};{};0",
+        level.name(),
+        summary.join(", "),
+        failed.len(), threshold(args.failed_warning.map(|n| n as u64)), args.failed_critical,
+        dangling.len(), args.dangling_warning, threshold(args.dangling_critical.map(|n| n as u64)),
+        threshold(stale_warning), threshold(stale_critical),
+    );
+
+    print!("{details}");
+
+    level as i32
+}
+
+/// Plugin output is expected to be a single line, details go after it.
+fn first_line(s: &str) -> &str {
+    s.lines().next().unwrap_or_default()
+}
+
+fn plural(n: usize, one: &str, many: &str) -> String {
+    match n {
+        1 => format!("{n} {one}"),
+        _ => format!("{n} {many}"),
+    }
+}
+
+fn format_age(secs: u64) -> String {
+    match secs {
+        0..60       => format!("{secs}s"),
+        60..3600    => format!("{}m", secs / 60),
+        3600..86400 => format!("{}h {}m", secs / 3600, secs / 60 % 60),
+        _           => format!("{}d {}h", secs / 86400, secs / 3600 % 24),
+    }
+}
+
+fn parse_age(s: &str) -> Result<Duration, String> {
+    let unit = s.trim_start_matches(|c: char| c.is_ascii_digit());
+    let n: u64 = s[..s.len() - unit.len()].parse()
+        .map_err(|_| format!("expected a number with an optional unit, like 25h, but got {s}"))?;
+
+    let secs = match unit {
+        "" | "s" => n,
+        "m" => n.saturating_mul(60),
+        "h" => n.saturating_mul(3600),
+        "d" => n.saturating_mul(86400),
+        _ => return Err(format!("expected a unit of s, m, h or d, but got {unit}")),
+    };
+
+    Ok(Duration::from_secs(secs))
+}
@@ -0,0 +1,175 @@
+use std::cell::RefCell;
+use std::ffi::OsStr;
+use std::fs::Permissions;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+use async_channel::{Receiver, Sender};
+use serde::Serialize;
+
+use run_stars_lib::control::{self, Command, REPLY_ERROR, REPLY_OK};
+use run_stars_lib::path::Root;
+use run_stars_lib::{StateChange, Task};
+
+pub enum Reply {
+    Done,
+    Status(String),
+    Events(Receiver<String>),
+}
+
+pub struct Request {
+    pub command: Command,
+    reply:       Sender<Result<Reply, String>>,
+}
+
+impl Request {
+    pub fn reply(self, result: Result<Reply, String>) {
+        let _ = self.reply.send_blocking(result);
+    }
+}
+
+/// Removes the socket file when the run is over
+/// and gives subscribers a chance to receive the last changes.
+pub struct Socket {
+    path:      PathBuf,
+    streaming: Arc<(Mutex<usize>, Condvar)>,
+}
+
+impl Drop for Socket {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+
+        let (streaming, finished) = &*self.streaming;
+
+        if let Ok(streaming) = streaming.lock() {
+            let _ = finished.wait_timeout_while(streaming, Duration::from_secs(1), |n| *n > 0);
+        }
+    }
+}
+
+/// Starts accepting commands for a run with a given state file name,
+/// each client is served on its own thread and requests are forwarded to the returned channel.
+pub fn listen(file_name: impl AsRef<OsStr>) -> io::Result<(Socket, Receiver<Request>)> {
+    let path = control::socket_path(Root::current(), file_name);
+
+    // Socket might be left behind by a runner that didn't exit gracefully
+    let _ = std::fs::remove_file(&path);
+
+    let listener = UnixListener::bind(&path)?;
+    std::fs::set_permissions(&path, Permissions::from_mode(0o600))?;
+
+    let (s, r) = async_channel::unbounded();
+
+    let streaming = Arc::new((Mutex::new(0), Condvar::new()));
+    let socket = Socket { path, streaming: streaming.clone() };
+
+    std::thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let s = s.clone();
+            let streaming = streaming.clone();
+
+            std::thread::spawn(move || serve(stream, s, &streaming));
+        }
+    });
+
+    Ok((socket, r))
+}
+
+fn serve(stream: UnixStream, s: Sender<Request>, streaming: &(Mutex<usize>, Condvar)) -> io::Result<()> {
+    // Client that stopped reading shouldn't keep a thread busy forever
+    stream.set_write_timeout(Some(Duration::from_secs(1)))?;
+
+    let mut writer = stream.try_clone()?;
+
+    for l in BufReader::new(stream).lines() {
+        let l = l?;
+
+        let result = match Command::from_str(l.trim()) {
+            Ok(command) => {
+                let (reply, r) = async_channel::bounded(1);
+
+                if s.send_blocking(Request { command, reply }).is_err() {
+                    return Ok(())
+                }
+
+                r.recv_blocking().unwrap_or_else(|_| Err("run has finished".to_owned()))
+            },
+            Err(e) => Err(e.to_string()),
+        };
+
+        match result {
+            Ok(Reply::Done) => writeln!(writer, "{REPLY_OK}")?,
+            Ok(Reply::Status(json)) => writeln!(writer, "{json}")?,
+            Ok(Reply::Events(events)) => {
+                let (n, finished) = streaming;
+
+                *n.lock().unwrap() += 1;
+
+                let result = stream_events(&mut writer, events);
+
+                *n.lock().unwrap() -= 1;
+                finished.notify_all();
+
+                return result
+            },
+            Err(e) => writeln!(writer, "{REPLY_ERROR} {e}")?,
+        }
+    }
+
+    Ok(())
+}
+
+fn stream_events(mut w: impl Write, events: Receiver<String>) -> io::Result<()> {
+    writeln!(w, "{REPLY_OK}")?;
+
+    while let Ok(json) = events.recv_blocking() {
+        writeln!(w, "{json}")?;
+    }
+
+    Ok(())
+}
+
+#[derive(Default)]
+pub struct Subscribers {
+    senders: RefCell<Vec<Sender<String>>>,
+}
+
+impl Subscribers {
+    pub fn subscribe(&self) -> Receiver<String> {
+        let (s, r) = async_channel::unbounded();
+        self.senders.borrow_mut().push(s);
+
+        r
+    }
+
+    pub fn broadcast(&self, json: &str) {
+        self.senders.borrow_mut().retain(|s| s.try_send(json.to_owned()).is_ok());
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.senders.borrow().is_empty()
+    }
+}
+
+#[derive(Serialize)]
+pub struct TaskEvent<'a> {
+    pub task: usize,
+    #[serde(serialize_with = "run_stars_lib::serialize_lossy")]
+    pub path: &'a Path,
+
+    #[serde(flatten)]
+    pub state: &'a StateChange,
+}
+
+#[derive(Serialize)]
+pub struct StatusReply<'a> {
+    #[serde(serialize_with = "run_stars_lib::serialize_lossy")]
+    pub path:  &'a Path,
+    pub tasks: &'a [Task],
+}
@@ -10,6 +10,18 @@ pub enum Error {
 
     #[error(transparent)]
     State(#[from] run_stars_lib::error::Error),
+
+    #[error("a directory with the tasks to execute is required, see --help")]
+    NoDirectory,
+
+    #[error("--json and --ndjson can't be used together, see --help")]
+    JsonFormat,
+
+    #[error("there is no state of a directory ({0})")]
+    UnknownDirectory(PathBuf),
+
+    #[error("couldn't print to the standard output\n{0}")]
+    Print(std::io::Error),
 }
 
 impl Debug for Error {
@@ -21,32 +33,8 @@ impl Debug for Error {
 
 #[derive(Error, Debug)]
 pub enum FileError {
-    #[error("unable to get an absolute path to the target directory ({path})\n{io}")]
-    Absolute {
-        io:   std::io::Error,
-        path: PathBuf,
-    },
-
-    #[error("unable to access a directory ({path})\n{io}")]
-    AccessLocation {
-        io:   std::io::Error,
-        path: PathBuf,
-    },
-
-    #[error("couldn't create a file ({path}) for the persistant state information\n{io}")]
-    CreatePersistant {
-        io:   std::io::Error,
-        path: PathBuf,
-    },
-
-    #[error("couldn't write a persistant state to a file ({path})\n{io}")]
-    WritePersistant {
-        io:   std::io::Error,
-        path: PathBuf,
-    },
-
-    #[error("couldn't sync the persistant state file to the location ({path})\n{io}")]
-    SyncPersistant {
+    #[error("couldn't write the metrics to a file ({path})\n{io}")]
+    WriteMetrics {
         io:   std::io::Error,
         path: PathBuf,
     },
@@ -0,0 +1,82 @@
+use run_stars_lib::path::{self, Root};
+use run_stars_lib::{migrate, repair};
+
+use crate::error::Error;
+
+#[derive(argh::FromArgs)]
+#[argh(subcommand, name = "gc")]
+/// Repair the states of runs that were interrupted, remove the states of directories that no longer exist
+/// and move or rename the states that were put or named by an older version.
+pub struct Args {
+    /// only print what would be done
+    #[argh(switch)]
+    dry_run: bool,
+}
+
+pub fn run(args: Args) -> Result<(), Error> {
+    if let Some(legacy) = migrate::legacy_location(Root::current()) {
+        let legacy = legacy.to_string_lossy();
+
+        match args.dry_run {
+            true  => println!("would move the states out of {legacy}, where an older version has put them"),
+            false => if migrate::migrate_location(Root::current())? {
+                println!("moved the states out of {legacy}, where an older version has put them");
+            },
+        }
+    }
+
+    // Listing of the states would move them into the right location and create the directories for them
+    let states = || match args.dry_run {
+        true  => run_stars_lib::states_of(Root::current()),
+        false => run_stars_lib::states(),
+    };
+
+    match args.dry_run {
+        true => states()?.iter()
+            .filter(|state| path::is_legacy(&state.file_name))
+            .for_each(|state| println!("would rename {}, it was named by an older version", state.path().to_string_lossy())),
+        false => match migrate::migrate(Root::current())? {
+            0 => {},
+            n => println!("renamed {n} states that were named by an older version"),
+        },
+    }
+
+    let mut states = states()?;
+    states.sort_by_key(|state| state.path());
+
+    for state in states.iter().filter(|state| !state.running) {
+        let path = state.path();
+
+        // Directory is gone only if the system says so, it might be just unreadable or unmounted,
+        // and the one of a state whose path was lost can't be checked at all
+        let gone = state.root.path_of(&state.file_name)
+            .is_some_and(|p| matches!(p.try_exists(), Ok(false)));
+
+        if gone {
+            match args.dry_run {
+                true  => println!("would remove {}, the directory no longer exists", path.to_string_lossy()),
+                false => if repair::remove(state)? {
+                    println!("removed {}, the directory no longer exists", path.to_string_lossy());
+                },
+            }
+
+            continue
+        }
+
+        if !state.runtime {
+            continue
+        }
+
+        match args.dry_run {
+            true => println!("would repair {}", path.to_string_lossy()),
+            false => match repair::repair(state)? {
+                Some(0) => println!("repaired {}", path.to_string_lossy()),
+                Some(n) => println!("repaired {}, {n} tasks were interrupted", path.to_string_lossy()),
+                // Runner has started again in the meantime
+                None => {},
+            },
+        }
+    }
+
+    Ok(())
+}
@@ -1,173 +1,315 @@
+mod check;
+mod control;
 mod error;
-mod ls;
+mod gc;
+mod metrics;
+mod status;
 
-use std::fs::File;
 use std::io::Write;
 use std::num::NonZeroUsize;
-use std::path::{self, Path, PathBuf};
-use std::process::Stdio;
+use std::os::unix::ffi::OsStrExt;
+use std::path::PathBuf;
 
-use async_process::Command;
+use futures_concurrency::future::{FutureExt, Join};
+use futures_lite::future::{self, FutureExt as _};
 
-use futures_concurrency::future::FutureExt;
-use futures_concurrency::prelude::{ConcurrentStream, IntoConcurrentStream};
-use futures_lite::future;
+use run_stars_lib::path::{Locations, Root};
+use run_stars_lib::pool::Pool;
+use run_stars_lib::Order;
+use run_stars_lib::run::{Control, Events, Execution, Runner, Slots, Symlinks, Traversal};
 
-use jiff::Timestamp;
-
-use run_stars_lib::{Status, StateChange, Task};
-use run_stars_lib::write::StateFile;
-
-use error::{Error, FileError};
+use control::{Reply, StatusReply, Subscribers, TaskEvent};
+use error::Error;
 
 #[derive(argh::FromArgs)]
 /// Batch executor with a convenient state reporting.
 struct Args {
-    /// directory that contains to be executed files
+    #[argh(subcommand)]
+    command: Option<Command>,
+
+    /// directories that contain to be executed files, every one of them has its own state
     #[argh(positional)]
-    dir: PathBuf,
+    dirs: Vec<PathBuf>,
+
+    /// run the directories at the same time instead of one after another, sharing the --limit between them
+    #[argh(switch)]
+    parallel: bool,
 
     /// print a relative path to an each file that will be executed
     #[argh(switch)]
     list: bool,
 
+    /// execute the files of every subdirectory too
+    #[argh(switch, short = 'r')]
+    recursive: bool,
+
+    /// execute the files of subdirectories up to this depth, 0 is only the top level
+    #[argh(option)]
+    max_depth: Option<usize>,
+
+    /// what to do with symbolic links: skip, files or follow
+    #[argh(option, default = "Symlinks::Skip")]
+    symlinks: Symlinks,
+
+    /// when to execute the files of subdirectories: depth-first or levels, where every level waits for the previous one
+    #[argh(option, default = "Traversal::DepthFirst")]
+    traversal: Traversal,
+
+    /// order of the files: name, natural, manifest, duration or shuffle, it's kept in the state
+    #[argh(option, default = "Order::Name")]
+    order: Order,
+
     /// limit the amount of simultaneously running tasks
     #[argh(option)]
     limit: Option<NonZeroUsize>,
 
+    /// share the limit of simultaneously running tasks with the other runners of this pool
+    #[argh(option)]
+    pool: Option<String>,
+
+    /// amount of tasks that the pool runs at the same time, the amount of CPUs by default
+    #[argh(option)]
+    pool_size: Option<NonZeroUsize>,
+
     /// reverse order of execution
     #[argh(switch)]
     reverse: bool,
-}
 
-fn main() -> Result<(), Error> {
-    let args: Args = argh::from_env();
-
-    let mut target_dir = path::absolute(&args.dir)
-        .map_err(|io| FileError::Absolute { path: args.dir, io })?;
+    /// don't listen for commands and status queries on a control socket
+    #[argh(switch)]
+    no_socket: bool,
 
-    let files = ls::files(&target_dir)
-        .map_err(|io| FileError::AccessLocation { path: target_dir.clone(), io })?;
+    /// append changes of tasks to the runtime state instead of rewriting it every time
+    #[argh(switch)]
+    journal: bool,
 
-    let mut tasks = vec![];
+    /// directory for the runtime states, instead of $RUN_STARS_RUNTIME_DIR or $XDG_RUNTIME_HOME/run_stars
+    #[argh(option)]
+    runtime_dir: Option<PathBuf>,
 
-    let (s, r) = async_channel::unbounded();
-    let mut processes: Vec<_> = files.map(|f| {
-        let p = f.path();
+    /// directory for the persistent states, instead of $RUN_STARS_STATE_DIR or $XDG_STATE_HOME/run_stars
+    #[argh(option)]
+    state_dir: Option<PathBuf>,
 
-        tasks.push(Task::new(p.clone()));
+    /// separate set of states inside of the state directories, instead of $RUN_STARS_NAMESPACE
+    #[argh(option)]
+    namespace: Option<String>,
+}
 
-        (tasks.len() - 1, p, s.clone())
-    }).collect();
+#[derive(argh::FromArgs)]
+#[argh(subcommand)]
+enum Command {
+    Status(status::Args),
+    Metrics(metrics::Args),
+    Check(check::Args),
+    Gc(gc::Args),
+}
 
-    drop(s);
+fn main() -> Result<(), Error> {
+    let args: Args = argh::from_env();
 
-    processes.sort_by(|a, b| a.1.cmp(&b.1));
+    Root::configure(Locations {
+        runtime_dir: args.runtime_dir,
+        state_dir:   args.state_dir,
+        namespace:   args.namespace,
+    })?;
+
+    match args.command {
+        Some(Command::Status(args))  => return status::run(args).map(|failed| if failed {
+            std::process::exit(1)
+        }),
+        Some(Command::Metrics(args)) => return metrics::run(args),
+        Some(Command::Check(args))   => std::process::exit(check::run(args)),
+        Some(Command::Gc(args))      => return gc::run(args),
+        None => {},
+    }
 
-    if args.reverse {
-        processes.reverse();
+    if args.dirs.is_empty() {
+        return Err(Error::NoDirectory)
     }
 
-    if args.list {
-        processes.iter().for_each(|(_, p, _)| println!("{}", p.to_string_lossy()));
+    let depth = match (args.max_depth, args.recursive) {
+        (Some(depth), _) => depth,
+        (None, true)     => usize::MAX,
+        (None, false)    => 0,
+    };
 
-        return Ok(())
+    let mut dirs: Vec<PathBuf> = Vec::with_capacity(args.dirs.len());
+
+    // Locks of runs belong to the process, so the second run of the same directory wouldn't wait for the first one
+    for dir in args.dirs {
+        let absolute = std::path::absolute(&dir).unwrap_or_else(|_| dir.clone());
+
+        if !dirs.iter().any(|known| std::path::absolute(known).is_ok_and(|known| known == absolute)) {
+            dirs.push(dir);
+        }
     }
 
-    target_dir = run_stars_lib::path::encode(target_dir);
+    let slots = match args.parallel {
+        true  => args.limit.map(Slots::new),
+        false => None,
+    };
 
-    let mut runtime_path = run_stars_lib::path::init_runtime_dir()?;
-    runtime_path.push(&target_dir);
+    let pool = match (&args.pool, args.list) {
+        (Some(name), false) => {
+            let size = args.pool_size
+                .or_else(|| std::thread::available_parallelism().ok())
+                .unwrap_or(NonZeroUsize::MIN);
 
-    let mut runtime: StateFile = match File::create(&runtime_path) {
-        Ok(fd) => StateFile::File(fd),
-        Err(_) => StateFile::Sink,
+            Some(Pool::join(Root::current(), name, size)?)
+        },
+        _ => None,
     };
 
-    runtime.lock().unwrap();
+    let runners = dirs.into_iter().map(|dir| {
+        let runner = Runner::new(dir)
+            .depth(depth)
+            .symlinks(args.symlinks)
+            .traversal(args.traversal)
+            .order(args.order)
+            .reverse(args.reverse)
+            // Runs are started one after another, so a busy directory would hold up the ones after it
+            .wait(!args.parallel)
+            .journal(args.journal)
+            .on_error(|e| eprintln!("{e:?}"));
+
+        let runner = match &pool {
+            Some(pool) => runner.pool(pool.clone()),
+            None => runner,
+        };
+
+        match &slots {
+            Some(slots) => runner.slots(slots.clone()),
+            None => runner.limit(args.limit),
+        }
+    });
 
-    let wait_for_processes = processes.into_co_stream().limit(args.limit).for_each(|(i, p, s)| {
-        async move {
-            let handle_error = |e: &std::io::Error| {
-                eprintln!("{}: {e}", &p.to_string_lossy());
-            };
+    if args.list {
+        let mut stdout = std::io::stdout().lock();
+
+        for runner in runners {
+            // Paths are printed as they are, so that the ones that aren't valid UTF-8 can still be used
+            for p in runner.plan()? {
+                match stdout.write_all(p.as_os_str().as_bytes()).and_then(|_| stdout.write_all(b"\n")) {
+                    Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => return Ok(()),
+                    Err(e) => return Err(Error::Print(e)),
+                    Ok(_) => {},
+                }
+            }
+        }
 
-            let c = Command::new(&p)
-                .stdout(Stdio::null())
-                .stderr(Stdio::null())
-                .spawn()
-                .inspect_err(handle_error);
+        return Ok(())
+    }
 
-            let (status, code) = match c {
-                Ok(_)  => (Status::Running, 0),
-                Err(_) => (Status::Failure, 1),
-            };
+    let mut errors = Vec::new();
 
-            let state = StateChange { status, code, time: Timestamp::now() };
-            s.send_blocking((i, state)).unwrap();
+    match args.parallel {
+        true => {
+            let executions: Vec<_> = runners
+                .filter_map(|runner| runner.start().map_err(|e| errors.push(Error::from(e))).ok())
+                .map(|(execution, events)| serve(execution, events, args.no_socket))
+                .collect();
 
-            let Ok(mut child) = c else { return };
+            errors.extend(future::block_on(executions.join()).into_iter().filter_map(Result::err));
+        },
+        false => for runner in runners {
+            let result = runner.start()
+                .map_err(Error::from)
+                .and_then(|(execution, events)| future::block_on(serve(execution, events, args.no_socket)));
 
-            let status = child.status().await
-                .inspect_err(handle_error);
+            if let Err(e) = result {
+                errors.push(e);
+            }
+        },
+    }
 
-            let (status, code) = match status {
-                Ok(status) if status.success() => (Status::Success, 0),
-                Ok(status) => (Status::Failure, status.code().unwrap_or(1)),
-                Err(_) => (Status::Failure, 1),
-            };
+    // Directories that have failed before the last one are only reported
+    let last = errors.pop();
 
-            let state = StateChange { status, code: code as u8, time: Timestamp::now() };
-            s.send_blocking((i, state)).unwrap();
-        }
-    });
+    for e in errors {
+        eprintln!("{e:?}");
+    }
 
-    let write_state = async {
-        let mut buffer = vec![];
+    match last {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
 
-        while let Ok(mut msg) = r.recv().await {
-            loop {
-                let (i, state) = msg;
+/// Executes a run, while its state can be queried and controlled through a socket.
+async fn serve(execution: Execution, events: Events, no_socket: bool) -> Result<(), Error> {
+    let control = execution.control();
+
+    let (_socket, requests) = match no_socket {
+        true  => (None, async_channel::unbounded().1),
+        false => match run_stars_lib::path::init_control_dir().map(|_| control::listen(control.file_name())) {
+            Ok(Ok((socket, requests))) => (Some(socket), requests),
+            Ok(Err(e)) => {
+                eprintln!("unable to listen for control commands\n↳ {e}");
+                (None, async_channel::unbounded().1)
+            },
+            Err(e) => {
+                eprintln!("{e:?}");
+                (None, async_channel::unbounded().1)
+            },
+        },
+    };
 
-                let t = &mut tasks[i];
-                t.status = state.status;
-                t.time = state.time;
+    let subscribers = Subscribers::default();
 
-                msg = match r.try_recv() {
-                    Ok(msg) => msg,
-                    Err(_) => break,
-                }
+    let broadcast = async {
+        while let Ok(event) = events.recv().await {
+            if subscribers.is_empty() {
+                continue
             }
 
-            if let Err(e) = run_stars_lib::write::write(&mut runtime, &mut buffer, &tasks) {
-                eprintln!("{e}");
+            let event = TaskEvent { task: event.task, path: &event.path, state: &event.change };
+
+            if let Ok(json) = serde_json::to_string(&event) {
+                subscribers.broadcast(&json);
             }
         }
-
-        buffer
     };
 
-    let (_, buffer) = future::block_on(wait_for_processes.join(write_state));
+    let handle_requests = async {
+        while let Ok(request) = requests.recv().await {
+            let result = handle_request(&control, &subscribers, &request.command);
 
-    drop(runtime);
-
-    write_persistant_state(&buffer, target_dir)?;
-
-    let _ = std::fs::remove_file(&runtime_path);
+            request.reply(result);
+        }
 
-    Ok(())
-}
+        future::pending().await
+    };
 
-fn write_persistant_state(b: &[u8], target: impl AsRef<Path>) -> Result<(), Error> {
-    let mut state_path = run_stars_lib::path::init_persistent_dir()?;
-    state_path.push(target);
+    let (result, _) = execution.join(broadcast).or(handle_requests).await;
 
-    let mut state = File::create(&state_path)
-        .map_err(|io| FileError::CreatePersistant { path: state_path.clone(), io })?;
+    drop(subscribers);
 
-    state.write_all(&b)
-        .map_err(|io| FileError::WritePersistant { path: state_path.clone(), io })?;
+    result.map(|_| ()).map_err(Error::from)
+}
 
-    state.sync_all()
-        .map_err(|io| FileError::SyncPersistant { path: state_path, io }.into())
+fn handle_request(control: &Control, subscribers: &Subscribers, command: &run_stars_lib::control::Command) -> Result<Reply, String> {
+    use run_stars_lib::control::Command;
+
+    match *command {
+        Command::Cancel(i) => control.cancel(i)
+            .map(|_| Reply::Done)
+            .map_err(|e| e.to_string()),
+        Command::Rerun(i) => control.rerun(i)
+            .map(|_| Reply::Done)
+            .map_err(|e| e.to_string()),
+        Command::Kill => {
+            control.kill();
+            Ok(Reply::Done)
+        },
+        Command::Status => {
+            let tasks = control.tasks();
+            let status = StatusReply { path: control.dir(), tasks: &tasks };
+
+            serde_json::to_string(&status)
+                .map(Reply::Status)
+                .map_err(|e| e.to_string())
+        },
+        Command::Subscribe => Ok(Reply::Events(subscribers.subscribe())),
+    }
 }
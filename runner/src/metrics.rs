@@ -0,0 +1,178 @@
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+use run_stars_lib::{State, Status, Task};
+use run_stars_lib::error::Error as StateError;
+
+use crate::error::{Error, FileError};
+
+#[derive(argh::FromArgs)]
+#[argh(subcommand, name = "metrics")]
+/// Export the states in the Prometheus text format.
+pub struct Args {
+    /// file to replace with the metrics, usually in a textfile collector directory of node_exporter
+    #[argh(option, short = 'o')]
+    output: Option<PathBuf>,
+}
+
+struct Dir {
+    path:    String,
+    running: bool,
+    tasks:   Vec<Task>,
+    /// Moment when each task has finished successfully for the last time
+    success: Vec<Option<f64>>,
+}
+
+impl Dir {
+    fn new(state: State) -> Self {
+        let tasks = match state.tasks() {
+            Ok((tasks, _)) => tasks,
+            Err(errors) => {
+                errors.iter()
+                    .filter(|e| !matches!(e, StateError::NotFound(_)))
+                    .for_each(|e| eprintln!("{e:?}"));
+
+                Vec::new()
+            },
+        };
+
+        let history = state.history()
+            .inspect_err(|e| eprintln!("{e:?}"))
+            .unwrap_or_default();
+
+        let success = tasks.iter()
+            .map(|task| history.iter()
+                .flatten()
+                .chain(std::iter::once(task))
+                .filter(|t| t.status == Status::Success && t.path == task.path)
+                .map(finished)
+                .reduce(f64::max))
+            .collect();
+
+        Dir {
+            path:    state.path().to_string_lossy().into_owned(),
+            running: state.running,
+            tasks,
+            success,
+        }
+    }
+}
+
+pub fn run(args: Args) -> Result<(), Error> {
+    let mut dirs: Vec<_> = run_stars_lib::states()?
+        .into_iter()
+        .map(Dir::new)
+        .collect();
+
+    dirs.sort_by(|a, b| a.path.cmp(&b.path));
+
+    let metrics = render(&dirs);
+
+    match args.output {
+        // Collector may read the file at any moment, so it has to be replaced at once
+        Some(p) => run_stars_lib::write::replace(&p, metrics.as_bytes(), false)
+            .map_err(|io| FileError::WriteMetrics { path: p, io })?,
+        None => match io::stdout().lock().write_all(metrics.as_bytes()) {
+            Err(e) if e.kind() != io::ErrorKind::BrokenPipe => return Err(Error::Print(e)),
+            _ => {},
+        },
+    }
+
+    Ok(())
+}
+
+fn render(dirs: &[Dir]) -> String {
+    let mut s = String::new();
+
+    let tasks = || dirs.iter().flat_map(|dir| dir.tasks.iter().enumerate().map(move |(i, task)| (dir, i, task)));
+
+    family(&mut s, "run_stars_task_status", "Status of a task in the last run, 1 for the current one");
+    for (dir, _, task) in tasks() {
+        for status in Status::ALL {
+            sample(&mut s, "run_stars_task_status", &[("dir", &dir.path), ("task", &label(task)), ("status", status.name())], (task.status == status) as u8);
+        }
+    }
+
+    family(&mut s, "run_stars_task_exit_code", "Exit code of a task in the last run");
+    for (dir, _, task) in tasks() {
+        sample(&mut s, "run_stars_task_exit_code", &[("dir", &dir.path), ("task", &label(task))], task.code);
+    }
+
+    family(&mut s, "run_stars_task_last_run_timestamp_seconds", "Moment when the last attempt of a task was started");
+    for (dir, _, task) in tasks() {
+        if let Some(start) = task.start {
+            sample(&mut s, "run_stars_task_last_run_timestamp_seconds", &[("dir", &dir.path), ("task", &label(task))], seconds(start.as_millisecond()));
+        }
+    }
+
+    family(&mut s, "run_stars_task_duration_seconds", "Duration of the last attempt of a task, up to now if it's still running");
+    for (dir, _, task) in tasks() {
+        if let Some(d) = task.duration() {
+            sample(&mut s, "run_stars_task_duration_seconds", &[("dir", &dir.path), ("task", &label(task))], d.as_secs_f64());
+        }
+    }
+
+    family(&mut s, "run_stars_task_last_success_timestamp_seconds", "Moment when a task has finished successfully for the last time, as far as the history goes");
+    for (dir, i, task) in tasks() {
+        if let Some(success) = dir.success[i] {
+            sample(&mut s, "run_stars_task_last_success_timestamp_seconds", &[("dir", &dir.path), ("task", &label(task))], success);
+        }
+    }
+
+    family(&mut s, "run_stars_tasks", "Amount of tasks in a directory by their status");
+    for dir in dirs {
+        for status in Status::ALL {
+            let n = dir.tasks.iter().filter(|task| task.status == status).count();
+            sample(&mut s, "run_stars_tasks", &[("dir", &dir.path), ("status", status.name())], n);
+        }
+    }
+
+    family(&mut s, "run_stars_running", "Whether a run of a directory is active right now");
+    for dir in dirs {
+        sample(&mut s, "run_stars_running", &[("dir", &dir.path)], dir.running as u8);
+    }
+
+    s
+}
+
+fn family(s: &mut String, name: &str, help: &str) {
+    let _ = writeln!(s, "# HELP {name} {help}");
+    let _ = writeln!(s, "# TYPE {name} gauge");
+}
+
+fn sample(s: &mut String, name: &str, labels: &[(&str, &str)], value: impl std::fmt::Display) {
+    let _ = write!(s, "{name}{{");
+
+    for (i, (key, value)) in labels.iter().enumerate() {
+        let separator = match i {
+            0 => "",
+            _ => ",",
+        };
+
+        let _ = write!(s, "{separator}{key}=\"{}\"", escape(value));
+    }
+
+    let _ = writeln!(s, "}} {value}");
+}
+
+#[inline]
+fn label(task: &Task) -> String {
+    task.path.to_string_lossy().into_owned()
+}
+
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[inline]
+fn seconds(milliseconds: i64) -> f64 {
+    milliseconds as f64 / 1000.0
+}
+
+#[inline]
+fn finished(task: &Task) -> f64 {
+    seconds(task.end.unwrap_or(task.time).as_millisecond())
+}
@@ -0,0 +1,221 @@
+use std::io::{self, IsTerminal, Write};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use jiff::tz::TimeZone;
+use serde::Serialize;
+
+use run_stars_lib::{format_duration, State, Status, Task};
+use run_stars_lib::error::Error;
+use run_stars_lib::path::printable;
+
+#[derive(argh::FromArgs)]
+#[argh(subcommand, name = "status")]
+/// Show the state of every known run, exits with 1 if anything has failed.
+pub struct Args {
+    /// show only this directory
+    #[argh(positional)]
+    dir: Option<PathBuf>,
+
+    /// show only the failed tasks and the runs that were interrupted
+    #[argh(switch)]
+    failed: bool,
+
+    /// print all states as a single JSON array
+    #[argh(switch)]
+    json: bool,
+
+    /// print an each state as a JSON object on its own line
+    #[argh(switch)]
+    ndjson: bool,
+
+    /// colorize the table: auto, always or never
+    #[argh(option, default = "Color::Auto")]
+    color: Color,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Color {
+    Auto,
+    Always,
+    Never,
+}
+
+impl FromStr for Color {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto"   => Ok(Color::Auto),
+            "always" => Ok(Color::Always),
+            "never"  => Ok(Color::Never),
+            _ => Err(format!("expected auto, always or never, but got {s}")),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Report {
+    #[serde(serialize_with = "run_stars_lib::serialize_lossy")]
+    path: PathBuf,
+
+    #[serde(flatten)]
+    state: State,
+
+    tasks:  Vec<Task>,
+    errors: Vec<String>,
+}
+
+impl Report {
+    fn new(state: State) -> Self {
+        // Only one of the state files has to exist
+        let (tasks, errors) = match state.tasks() {
+            Ok((tasks, errors)) => (tasks, errors),
+            Err(errors) => (Vec::new(), errors),
+        };
+
+        let errors = errors.into_iter()
+            .filter(|e| !matches!(e, Error::NotFound(_)))
+            .map(|e| e.to_string())
+            .collect();
+
+        Report { path: state.path(), state, tasks, errors }
+    }
+
+    /// Runtime state is left behind by a runner that has stopped without reporting the end.
+    #[inline]
+    fn is_dangling(&self) -> bool {
+        self.state.runtime && !self.state.running
+    }
+
+    fn has_failed(&self) -> bool {
+        self.is_dangling() || self.tasks.iter().any(|task| task.status == Status::Failure)
+    }
+}
+
+/// Returns whether any of the shown runs has failed.
+pub fn run(args: Args) -> Result<bool, crate::error::Error> {
+    if args.json && args.ndjson {
+        return Err(crate::error::Error::JsonFormat)
+    }
+
+    let dir = args.dir
+        .map(|dir| std::path::absolute(&dir).unwrap_or(dir));
+
+    let mut reports: Vec<_> = run_stars_lib::states()?
+        .into_iter()
+        .filter(|state| dir.as_ref().is_none_or(|dir| state.path() == *dir))
+        .map(Report::new)
+        .collect();
+
+    if let Some(dir) = dir.filter(|_| reports.is_empty()) {
+        return Err(crate::error::Error::UnknownDirectory(dir))
+    }
+
+    reports.sort_by(|a, b| a.path.cmp(&b.path));
+
+    if args.failed {
+        reports.iter_mut().for_each(|report| report.tasks.retain(|task| task.status == Status::Failure));
+        reports.retain(Report::has_failed);
+    }
+
+    let failed = reports.iter().any(Report::has_failed);
+
+    let color = match args.color {
+        Color::Auto => io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none(),
+        Color::Always => true,
+        Color::Never => false,
+    };
+
+    let mut stdout = io::stdout().lock();
+
+    let result = match (args.json, args.ndjson) {
+        (true, _) => serde_json::to_writer_pretty(&mut stdout, &reports)
+            .map_err(io::Error::from)
+            .and_then(|_| writeln!(stdout)),
+        (_, true) => reports.iter().try_for_each(|report| {
+            serde_json::to_writer(&mut stdout, report)?;
+            writeln!(stdout)
+        }),
+        _ => reports.iter().try_for_each(|report| print_table(&mut stdout, report, color)),
+    };
+
+    // Closed pipe is how the reader says it had enough
+    match result.and_then(|_| stdout.flush()) {
+        Err(e) if e.kind() != io::ErrorKind::BrokenPipe => Err(crate::error::Error::Print(e)),
+        _ => Ok(failed),
+    }
+}
+
+mod style {
+    pub const RESET:  &str = "\x1b[0m";
+    pub const BOLD:   &str = "\x1b[1m";
+    pub const DIM:    &str = "\x1b[2m";
+    pub const RED:    &str = "\x1b[31m";
+    pub const GREEN:  &str = "\x1b[32m";
+    pub const YELLOW: &str = "\x1b[33m";
+    pub const BLUE:   &str = "\x1b[34m";
+}
+
+fn print_table(w: &mut impl Write, report: &Report, color: bool) -> io::Result<()> {
+    let paint = |style: &'static str| match color {
+        true  => (style, style::RESET),
+        false => ("", ""),
+    };
+
+    let (bold, reset) = paint(style::BOLD);
+    write!(w, "{bold}{}{reset}", printable(&report.path))?;
+
+    match (report.state.running, report.is_dangling()) {
+        (true, _) => {
+            let (blue, reset) = paint(style::BLUE);
+            writeln!(w, " {blue}running{reset}")?;
+        },
+        (_, true) => {
+            let (yellow, reset) = paint(style::YELLOW);
+            writeln!(w, " {yellow}interrupted{reset}")?;
+        },
+        _ => writeln!(w)?,
+    }
+
+    let names: Vec<_> = report.tasks.iter()
+        .map(|task| match task.path.strip_prefix(&report.path) {
+            Ok(p) => printable(p),
+            Err(_) => printable(&task.path),
+        })
+        .collect();
+
+    let width = names.iter().map(|name| name.chars().count()).max().unwrap_or_default();
+
+    for (task, name) in report.tasks.iter().zip(names) {
+        let (status, reset) = paint(match task.status {
+            Status::Success   => style::GREEN,
+            Status::Failure   => style::RED,
+            Status::Cancelled => style::YELLOW,
+            Status::Running   => style::BLUE,
+            Status::Waiting | Status::Unknown => style::DIM,
+        });
+
+        let (dim, _) = paint(style::DIM);
+
+        let time = task.time.to_zoned(TimeZone::system()).strftime("%Y-%m-%d %H:%M:%S");
+        let duration = task.duration().map(format_duration).unwrap_or_default();
+
+        write!(w, "  {status}{:<9}{reset}  {time}  {duration:>9}  {name}", task.status.name())?;
+
+        match task.message.is_empty() {
+            true  => writeln!(w)?,
+            false => {
+                let padding = width - name.chars().count();
+                writeln!(w, "{:padding$}  {dim}{}{reset}", "", task.message)?
+            },
+        }
+    }
+
+    for e in &report.errors {
+        let (red, reset) = paint(style::RED);
+        writeln!(w, "  {red}{}{reset}", e.replace('\n', " "))?;
+    }
+
+    Ok(())
+}
@@ -1,7 +1,9 @@
 use std::io;
-use std::ffi::OsStr;
+use std::ffi::{OsStr, OsString};
 use std::fmt::Display;
-use std::os::unix::ffi::OsStringExt;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
 
 use futures_lite::{FutureExt, StreamExt};
 use futures_time::time::Duration;
@@ -14,10 +16,15 @@ use jiff::{Span, Timestamp};
 use ratatui::Terminal;
 use ratatui::backend::Backend;
 
+use run_stars_lib::control::Command;
 use run_stars_lib::error::Error;
-use run_stars_lib::{State, Status, Task};
+use run_stars_lib::monitor::Monitor;
+use run_stars_lib::path::{printable, Root};
+use run_stars_lib::{Reader, State, Status, Task};
 
+use crate::detail::Detail;
 use crate::handler::Handler;
+use crate::output::Output;
 use crate::render::{render, Selection, StateList, TaskTable, UI};
 use crate::spinner::{self, Spinner};
 
@@ -27,17 +34,29 @@ pub struct App {
     pub state_entries: Vec<StateEntry>, 
     pub task_entries:  Vec<TaskEntry>,
     pub last_error:    Option<ErrorEntry>,
+    pub output:        Option<Output>,
+    pub detail:        Option<Detail>,
+
+    /// Follows the state of the selected directory, so that only the appended changes are parsed
+    reader: Option<Reader>,
+    /// Users whose states are shown, the current one is always first
+    roots:  Vec<Root>,
+    /// Options that point a started runner to the same locations
+    runner_args: Vec<OsString>,
 }
 
 pub struct StateEntry {
     pub name: String,
+    /// Shown only when the states of several users are listed
+    pub owner: Option<String>,
     pub state: State,
 }
 
-impl From<State> for StateEntry {
-    fn from(state: State) -> Self {
+impl StateEntry {
+    fn new(state: State, roots: &[Root]) -> Self {
         StateEntry {
-            name: state.path().to_string_lossy().to_string(),
+            name: printable(state.path()),
+            owner: (roots.len() > 1).then(|| state.root.owner.clone()),
             state,
         }
     }
@@ -47,15 +66,23 @@ pub struct TaskEntry {
     pub status:  Status,
     pub path:    String,
     pub time:    String,
+    pub task:    Task,
 
     pub spinner: Spinner,
 }
 
 pub enum Action {
     Tick,
-    AddState(State),
-    RemoveState(State),
+    Resync,
+    UpdateState(State),
     RefreshTasks,
+    OpenOutput,
+    CloseOutput,
+    RefreshOutput,
+    OpenDetail,
+    CloseDetail,
+    Control(Command),
+    StartRun,
     Quit,
 }
 
@@ -63,16 +90,51 @@ impl From<Task> for TaskEntry {
     fn from(task: Task) -> Self {
         TaskEntry {
             status: task.status,
-            path:   unsafe {
-                let v = task.path.into_os_string().into_vec();
-                String::from_utf8_unchecked(v)
-            },
-            time: task.time.to_zoned(TimeZone::system()).strftime("%a %b %e %I:%M:%S %p").to_string(),
+            path:   printable(&task.path),
+            time: format_time(task.time),
+            task,
             spinner: Spinner::new(spinner::BRAILE),
         }
     }
 }
 
+/// Shows the paths relative to the directory of the state, replacing the directories
+/// that a path shares with the previous one by an indentation, so that the tasks of subdirectories look like a tree.
+fn tree(entries: &mut [TaskEntry], dir: &Path) {
+    let mut parents: Vec<String> = Vec::new();
+
+    for entry in entries {
+        let Ok(relative) = entry.task.path.strip_prefix(dir) else {
+            parents.clear();
+            continue
+        };
+
+        let mut components: Vec<String> = relative.iter().map(printable).collect();
+
+        let Some(name) = components.pop() else {
+            continue
+        };
+
+        let shared = parents.iter().zip(&components).take_while(|(a, b)| a == b).count();
+        let indent: usize = components[..shared].iter().map(|dir| dir.chars().count() + 1).sum();
+
+        entry.path = " ".repeat(indent);
+
+        for dir in &components[shared..] {
+            entry.path.push_str(dir);
+            entry.path.push('/');
+        }
+
+        entry.path.push_str(&name);
+
+        parents = components;
+    }
+}
+
+pub fn format_time(t: Timestamp) -> String {
+    t.to_zoned(TimeZone::system()).strftime("%a %b %e %I:%M:%S %p").to_string()
+}
+
 pub enum Severity {
     High,
     Low,
@@ -99,18 +161,10 @@ impl ErrorEntry {
 }
 
 impl App {
-    pub fn new(dir: Option<String>) -> Result<Self, Error> {
+    pub fn new(dir: Option<String>, roots: Vec<Root>, runner_args: Vec<OsString>) -> Self {
         let mut selection = Selection::StateList;
 
-        let mut state_entries: Vec<_> = run_stars_lib::states()?
-            .into_iter()
-            .map(|state| StateEntry {
-                name: state.path().to_string_lossy().to_string(),
-                state,
-            })
-            .collect();
-
-        state_entries.sort_by(|a, b| a.name.partial_cmp(&b.name).unwrap());
+        let (state_entries, errors) = state_entries(&roots);
 
         if state_entries.len() == 1 {
             selection = Selection::TaskTable;
@@ -126,69 +180,80 @@ impl App {
             state_entries,
             task_entries: Vec::new(),
             last_error:  None,
+            output:      None,
+            detail:      None,
+            reader:      None,
+            roots,
+            runner_args,
         };
 
-        if let Some(dir) = dir {
-            app.state_entries.iter()
-                .position(|entry| entry.name == dir)
-                .map(|i| app.ui.state_list.select(i));
+        if let Some(i) = dir.and_then(|dir| app.state_entries.iter().position(|entry| entry.name == dir)) {
+            app.ui.state_list.select(i);
         }
 
         app.refresh_tasks();
 
-        Ok(app)
+        if !errors.is_empty() {
+            app.set_error(errors.last(), Severity::High);
+        }
+
+        app
     }
 
-    fn add_state_unchecked(&mut self, state: State) {
-        let existing = self.state_entries.iter_mut()
-            .find(|entry| entry.state == state);
+    /// Lists the states again, keeping the selection on the same state if it's still there.
+    fn resync(&mut self) {
+        let (state_entries, errors) = state_entries(&self.roots);
 
-        match existing {
-            Some(entry) => entry.state.add(&state),
-            None => {
-                // TODO: Sort and make sure that selection wasn't moved
-                self.state_entries.push(state.into());
-                // self.state_entries.sort_by(|a, b| a.file_name.(b.file_name));
-                self.ui.state_list.len += 1;
-            },
-        } 
+        let selected = self.selected_state()
+            .and_then(|selected| state_entries.iter().position(|entry| entry.state == selected.state))
+            .unwrap_or_else(|| self.ui.state_list.selected().min(state_entries.len().saturating_sub(1)));
+
+        self.ui.state_list.len = state_entries.len();
+        self.ui.state_list.select(selected);
+        self.state_entries = state_entries;
+
+        self.refresh_tasks();
+
+        if !errors.is_empty() {
+            self.set_error(errors.last(), Severity::High);
+        }
     }
 
-    fn remove_state_unchecked(&mut self, state: State) {
+    /// Replaces what is known about a state, adding or removing its entry if needed.
+    fn update_state(&mut self, state: State) {
         let existing = self.state_entries.iter_mut()
             .find_position(|entry| entry.state == state);
 
-        if let Some((i, entry)) = existing {
-            entry.state.sub(&state);
-
-            match entry.state.exists() {
-                true => if state.running {
-                    self.task_entries.iter_mut().for_each(|e| {
-                        if e.status == Status::Running {
-                            e.status = Status::Unknown
-                        }
-                    })
-                }
-                false => {
-                    self.state_entries.remove(i);
-                    self.ui.state_list.len -= 1;
+        match existing {
+            Some((i, _)) if !state.exists() => {
+                self.state_entries.remove(i);
+                self.ui.state_list.len -= 1;
 
-                    if i == self.state_entries.len() {
-                        self.ui.state_list.previous();
-                    }
+                // Selection stays on the same state, unless it was the one that is removed
+                let selected = self.ui.state_list.selected();
 
-                    self.refresh_tasks();
+                if i < selected || selected == self.state_entries.len() {
+                    self.ui.state_list.select(selected.saturating_sub(1));
                 }
-            }
-        } 
+
+                self.refresh_tasks();
+            },
+            Some((_, entry)) => entry.state = state,
+            // TODO: Sort and make sure that selection wasn't moved
+            None if state.exists() => {
+                self.state_entries.push(StateEntry::new(state, &self.roots));
+                self.ui.state_list.len += 1;
+            },
+            None => {},
+        }
     }
 
     pub fn selected_state(&self) -> Option<&StateEntry> {
         self.state_entries.get(self.ui.state_list.selected())
     }
 
-    pub fn is_selected_state(&self, file_name: &OsStr) -> bool {
-        self.selected_state().is_some_and(|entry| entry.state.file_name == file_name)
+    pub fn is_selected_state(&self, root: &Root, file_name: &OsStr) -> bool {
+        self.selected_state().is_some_and(|entry| entry.state.root == *root && entry.state.file_name == file_name)
     }
 
     fn refresh_tasks(&mut self) {
@@ -199,9 +264,24 @@ impl App {
         };
 
         let running = entry.state.runtime && entry.state.running;
+        let dir = entry.state.path();
+
+        let (p, errors) = match entry.state.current() {
+            Ok(current) => current,
+            Err(errors) => {
+                self.set_error(errors.last(), Severity::High);
+                self.ui.task_table.set_len(0);
+                return
+            },
+        };
+
+        let mut reader = match self.reader.take() {
+            Some(reader) if reader.path() == p => reader,
+            _ => Reader::new(p),
+        };
 
-        match entry.state.tasks() {
-            Ok((tasks, errors)) => {
+        match reader.read() {
+            Ok(tasks) => {
                 self.task_entries.extend(tasks.into_iter().map(|mut task| {
                     if task.status == Status::Running && !running  {
                         task.status = Status::Unknown;
@@ -210,16 +290,167 @@ impl App {
                     TaskEntry::from(task)
                 }));
 
+                tree(&mut self.task_entries, &dir);
+
                 self.set_error(errors.last(), Severity::Low);
             },
-            Err(errors) => {
-                self.set_error(errors.last(), Severity::High);
+            Err(e) => {
+                self.set_error(Some(e), Severity::High);
             },
         }
 
+        self.reader = Some(reader);
         self.ui.task_table.set_len(self.task_entries.len());
     }
 
+    fn open_output(&mut self, monitor: &mut Monitor) {
+        let Some(state) = self.selected_state() else {
+            return
+        };
+
+        let Some(task) = self.ui.task_table.selected().and_then(|i| self.task_entries.get(i)) else {
+            return
+        };
+
+        let p = state.state.root.log_path(&state.state.file_name, state.state.path(), &task.task.path);
+
+        self.output = Some(Output::new(task.path.clone(), p));
+        self.watch_output(monitor);
+    }
+
+    /// Output file doesn't exist until the task is started, so this is retried on every change of the state.
+    fn watch_output(&mut self, monitor: &mut Monitor) {
+        let Some(output) = self.output.as_mut() else {
+            return
+        };
+
+        if output.watched {
+            return
+        }
+
+        match monitor.watch_output(&output.path) {
+            Ok(_) => {
+                output.watched = true;
+                self.refresh_output();
+            },
+            Err(io) if io.kind() == io::ErrorKind::NotFound => {},
+            Err(io) => {
+                let e = format!("unable to follow the output ({})\n{io}", output.path.to_string_lossy());
+                self.set_error(Some(e), Severity::Low);
+            },
+        }
+    }
+
+    fn close_output(&mut self, monitor: &mut Monitor) {
+        monitor.unwatch_output();
+        self.output = None;
+    }
+
+    fn open_detail(&mut self) {
+        let Some(state) = self.selected_state() else {
+            return
+        };
+
+        let Some((i, task)) = self.ui.task_table.selected().and_then(|i| self.task_entries.get(i).map(|task| (i, task))) else {
+            return
+        };
+
+        let (history, e) = match state.state.history() {
+            Ok(runs) => {
+                let history = runs.into_iter()
+                    .rev()
+                    .filter_map(|run| run.into_iter().find(|t| t.path == task.task.path))
+                    .collect();
+
+                (history, None)
+            },
+            Err(e) => (Vec::new(), Some(e)),
+        };
+
+        let log = state.state.root.log_path(&state.state.file_name, state.state.path(), &task.task.path);
+
+        self.detail = Some(Detail::new(i, history, log));
+        self.set_error(e, Severity::Low);
+        self.refresh_detail();
+    }
+
+    fn refresh_detail(&mut self) {
+        let Some(detail) = self.detail.as_mut() else {
+            return
+        };
+
+        if detail.task >= self.task_entries.len() {
+            self.detail = None;
+            return
+        }
+
+        if let Err(io) = detail.read_tail() {
+            self.set_error(Some(format!("unable to read the output\n{io}")), Severity::Low);
+        }
+    }
+
+    fn refresh_output(&mut self) {
+        let Some(output) = self.output.as_mut() else {
+            return
+        };
+
+        if let Err(io) = output.read() {
+            let e = format!("unable to read the output ({})\n{io}", output.path.to_string_lossy());
+            self.set_error(Some(e), Severity::Low);
+        }
+    }
+
+    fn control(&mut self, command: Command) {
+        let Some(entry) = self.selected_state() else {
+            return
+        };
+
+        if let Err(e) = run_stars_lib::control::send(&entry.state.root, &entry.state.file_name, &command) {
+            self.set_error(Some(e), Severity::Low);
+        }
+    }
+
+    /// Runner is detached into its own process group, so it outlives the TUI.
+    fn start_run(&mut self) {
+        let Some(entry) = self.selected_state() else {
+            return
+        };
+
+        if entry.state.running {
+            return self.set_error(Some("tasks of this directory are already running"), Severity::Low);
+        }
+
+        // Runner would write into the states of the current user instead
+        if !entry.state.root.is_current() {
+            return self.set_error(Some(format!("tasks of {} can't be started by another user", entry.state.root.owner)), Severity::Low);
+        }
+
+        let dir = entry.state.path();
+
+        // Tasks of the subdirectories are found again only if the runner descends as deep as the last run did
+        let depth = self.task_entries.iter()
+            .filter_map(|entry| entry.task.path.strip_prefix(&dir).ok())
+            .map(|p| p.components().count().saturating_sub(1))
+            .max()
+            .unwrap_or(0);
+
+        let spawned = std::process::Command::new(runner_executable())
+            .args(&self.runner_args)
+            .arg("--max-depth")
+            .arg(depth.to_string())
+            .arg("--")
+            .arg(dir)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .process_group(0)
+            .spawn();
+
+        if let Err(e) = spawned {
+            self.set_error(Some(format!("unable to start a runner\n{e}")), Severity::Low);
+        }
+    }
+
     pub fn set_error(&mut self, e: Option<impl Display>, severity: Severity) {
         self.last_error = e.map(|e| ErrorEntry::new(e, severity));
     }
@@ -229,16 +460,39 @@ impl App {
     }
 }
 
+/// States of every root, the ones that couldn't be listed are reported along with them.
+fn state_entries(roots: &[Root]) -> (Vec<StateEntry>, Vec<Error>) {
+    let (states, errors) = run_stars_lib::all_states(roots);
+
+    let mut state_entries: Vec<_> = states.into_iter()
+        .map(|state| StateEntry::new(state, roots))
+        .collect();
+
+    state_entries.sort_by(|a, b| a.name.cmp(&b.name).then_with(|| a.owner.cmp(&b.owner)));
+
+    (state_entries, errors)
+}
+
+/// Prefers a runner that was installed alongside of the TUI, before looking in the $PATH.
+fn runner_executable() -> PathBuf {
+    const RUNNER: &str = "run-stars";
+
+    std::env::current_exe().ok()
+        .and_then(|p| p.parent().map(|dir| dir.join(RUNNER)))
+        .filter(|p| p.is_file())
+        .unwrap_or_else(|| PathBuf::from(RUNNER))
+}
+
 pub enum Event<FS, UI> {
     FS(FS),
     UI(UI),
 }
 
-pub async fn run<B: Backend>(terminal: &mut Terminal<B>, mut app: App) -> io::Result<()> {
+pub async fn run<B: Backend>(terminal: &mut Terminal<B>, mut app: App) -> Result<(), Box<dyn std::error::Error>> {
     use futures_time::prelude::*;
 
     let mut ui_events = crossterm::event::EventStream::new();
-    let mut fs_events = run_stars_lib::monitor::monitor().unwrap();
+    let (mut monitor, mut fs_events) = run_stars_lib::changes::changes(app.roots.clone())?;
 
     let mut timeout: Duration = Duration::from_millis(100);
     let mut interval = Interval::new(timeout.into());
@@ -261,9 +515,37 @@ pub async fn run<B: Backend>(terminal: &mut Terminal<B>, mut app: App) -> io::Re
 
         match action {
             Action::Tick => {},
-            Action::RefreshTasks => app.refresh_tasks(),
-            Action::AddState(state) => app.add_state_unchecked(state),
-            Action::RemoveState(state) => app.remove_state_unchecked(state),
+            Action::Resync => {
+                app.resync();
+                app.watch_output(&mut monitor);
+                app.refresh_detail();
+            },
+            Action::RefreshTasks => {
+                app.refresh_tasks();
+                app.watch_output(&mut monitor);
+                app.refresh_detail();
+            },
+            Action::OpenOutput => {
+                app.detail = None;
+                app.open_output(&mut monitor);
+            },
+            Action::CloseOutput => app.close_output(&mut monitor),
+            Action::RefreshOutput => app.refresh_output(),
+            Action::OpenDetail => app.open_detail(),
+            Action::CloseDetail => app.detail = None,
+            Action::UpdateState(state) => {
+                let refresh = app.is_selected_state(&state.root, &state.file_name);
+
+                app.update_state(state);
+
+                if refresh {
+                    app.refresh_tasks();
+                    app.watch_output(&mut monitor);
+                    app.refresh_detail();
+                }
+            },
+            Action::Control(command) => app.control(command),
+            Action::StartRun => app.start_run(),
             Action::Quit => break,
         }
 
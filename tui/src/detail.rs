@@ -0,0 +1,72 @@
+use std::io;
+use std::path::PathBuf;
+
+use run_stars_lib::Task;
+
+use crate::output::Output;
+
+/// Amount of the last output lines that are shown.
+const TAIL: usize = 10;
+
+pub struct Detail {
+    /// Index of the task in the task table
+    pub task:    usize,
+    /// Same task in the previous runs, from the newest to the oldest
+    pub history: Vec<Task>,
+    pub tail:    Vec<String>,
+    pub scroll:  u16,
+
+    /// Log of the task, that is read only as far as it has grown since the last time
+    output: Output,
+}
+
+impl Detail {
+    pub fn new(task: usize, history: Vec<Task>, log: PathBuf) -> Self {
+        Detail {
+            task,
+            history,
+            tail:   Vec::new(),
+            scroll: 0,
+            output: Output::new(String::new(), log),
+        }
+    }
+
+    /// Output file doesn't exist until the task is started, that's not an error.
+    pub fn read_tail(&mut self) -> io::Result<()> {
+        let output = &mut self.output;
+
+        match output.read() {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+            Ok(_) => {},
+        }
+
+        let partial = output.partial();
+        let skip = (output.lines.len() + partial.is_some() as usize).saturating_sub(TAIL);
+
+        self.tail = output.lines.iter()
+            .cloned()
+            .chain(partial)
+            .skip(skip)
+            .collect();
+
+        Ok(())
+    }
+
+    #[inline]
+    pub fn scroll_up(&mut self) {
+        self.scroll = self.scroll.saturating_sub(1);
+    }
+
+    #[inline]
+    pub fn scroll_down(&mut self) {
+        self.scroll = self.scroll.saturating_add(1);
+    }
+}
+
+pub fn format_memory(kilobytes: u64) -> String {
+    match kilobytes {
+        0..1024 => format!("{kilobytes} KiB"),
+        _       => format!("{:.1} MiB", kilobytes as f64 / 1024.0),
+    }
+}
@@ -1,48 +1,38 @@
 use crossterm::event::{KeyCode, KeyEvent, KeyEventKind};
-use run_stars_lib::{monitor::{self, StateEvent}, path, State};
+use run_stars_lib::{changes::Change, control::Command, error::MonitorError};
 
-use crate::app::{Action, App};
+use crate::app::{Action, App, Severity};
 
 pub trait Handler {
-    fn handle_fs(&mut self, event: Option<StateEvent>) -> Action;
+    fn handle_fs(&mut self, event: Option<Result<Change, MonitorError>>) -> Action;
     fn handle_ui(&mut self, event: Option<Result<crossterm::event::Event, std::io::Error>>) -> Action;
     fn handle_keyboard(&mut self, key: KeyEvent) -> Action;
+    fn handle_output_keyboard(&mut self, key: KeyEvent) -> Action;
+    fn handle_detail_keyboard(&mut self, key: KeyEvent) -> Action;
 }
 
 impl Handler for App {
-    fn handle_fs(&mut self, event: Option<StateEvent>) -> Action {
-        let Some(io_event) = event else {
-            return Action::Tick
+    fn handle_fs(&mut self, event: Option<Result<Change, MonitorError>>) -> Action {
+        let change = match event {
+            Some(Ok(change)) => change,
+            Some(Err(e)) => {
+                self.set_error(Some(e), Severity::Low);
+                return Action::Tick
+            },
+            None => return Action::Tick,
         };
 
-        let state = State::new(io_event.file_name);
-
-        match io_event.event {
-            monitor::Event::Modified => {
-                match self.is_selected_state(state.file_name.as_os_str()) {
-                    true  => Action::RefreshTasks,
-                    false => Action::Tick,
-                }
-            },
-            monitor::Event::New => {
-                match io_event.kind {
-                    path::Kind::Runtime => Action::AddState(state.runtime().running()),
-                    path::Kind::Persistent => Action::AddState(state.persistent()),
-                }
-            },
-            monitor::Event::Removed  => {
-                match io_event.kind {
-                    path::Kind::Runtime => Action::RemoveState(state.runtime().running()),
-                    path::Kind::Persistent => Action::RemoveState(state.persistent()),
-                }
+        match change {
+            Change::RunStarted(state)
+            | Change::RunFinished(state)
+            | Change::Dangling(state)
+            | Change::Removed(state) => Action::UpdateState(state),
+            Change::TaskChanged { root, file_name, .. } => match self.is_selected_state(&root, &file_name) {
+                true  => Action::RefreshTasks,
+                false => Action::Tick,
             },
-            monitor::Event::Closed => {
-                match io_event.kind {
-                    path::Kind::Runtime => Action::RemoveState(state.running()),
-                    _                   => Action::Tick,
-                }
-            }
-            monitor::Event::Unknown => Action::Tick,
+            Change::Output => Action::RefreshOutput,
+            Change::Resync => Action::Resync,
         }
     }
 
@@ -60,6 +50,14 @@ impl Handler for App {
     }
 
     fn handle_keyboard(&mut self, key: KeyEvent) -> Action {
+        if self.output.is_some() {
+            return self.handle_output_keyboard(key)
+        }
+
+        if self.detail.is_some() {
+            return self.handle_detail_keyboard(key)
+        }
+
         if key.kind == KeyEventKind::Press {
             return match key.code {
                 KeyCode::Char('q') | KeyCode::Esc   => self.quit(),
@@ -68,10 +66,77 @@ impl Handler for App {
                 KeyCode::Char('h') | KeyCode::Left  => self.ui.focus_state_list(),
                 KeyCode::Char('l') | KeyCode::Right => self.ui.focus_task_table(),
                 KeyCode::Tab                        => self.ui.switch(),
+                KeyCode::Char('s')                  => Action::StartRun,
+                KeyCode::Char('x')                  => Action::Control(Command::Kill),
+                KeyCode::Char('c')                  => self.ui.task_table.selected()
+                    .map_or(Action::Tick, |i| Action::Control(Command::Cancel(i))),
+                KeyCode::Char('r')                  => self.ui.task_table.selected()
+                    .map_or(Action::Tick, |i| Action::Control(Command::Rerun(i))),
+                KeyCode::Char('o')                  => Action::OpenOutput,
+                KeyCode::Enter                      => Action::OpenDetail,
                 _                                   => Action::Tick
             }
         }
 
         Action::Tick
     }
+
+    fn handle_output_keyboard(&mut self, key: KeyEvent) -> Action {
+        let Some(output) = self.output.as_mut() else {
+            return Action::Tick
+        };
+
+        if key.kind != KeyEventKind::Press {
+            return Action::Tick
+        }
+
+        if let Some(input) = output.input.as_mut() {
+            match key.code {
+                KeyCode::Enter     => output.submit_search(),
+                KeyCode::Esc       => output.input = None,
+                KeyCode::Backspace => { input.pop(); },
+                KeyCode::Char(c)   => input.push(c),
+                _                  => {},
+            }
+
+            return Action::Tick
+        }
+
+        match key.code {
+            KeyCode::Char('o') | KeyCode::Char('q') | KeyCode::Esc => return Action::CloseOutput,
+            KeyCode::Char('j') | KeyCode::Down     => output.scroll_down(1),
+            KeyCode::Char('k') | KeyCode::Up       => output.scroll_up(1),
+            KeyCode::PageDown                      => output.scroll_down(output.height),
+            KeyCode::PageUp                        => output.scroll_up(output.height),
+            KeyCode::Char('g') | KeyCode::Home     => output.scroll_top(),
+            KeyCode::Char('G') | KeyCode::End      => output.follow(),
+            KeyCode::Char('w')                     => output.toggle_wrap(),
+            KeyCode::Char('/')                     => output.input = Some(String::new()),
+            KeyCode::Char('n')                     => output.next_match(true),
+            KeyCode::Char('N')                     => output.next_match(false),
+            _                                      => {},
+        }
+
+        Action::Tick
+    }
+
+    fn handle_detail_keyboard(&mut self, key: KeyEvent) -> Action {
+        let Some(detail) = self.detail.as_mut() else {
+            return Action::Tick
+        };
+
+        if key.kind != KeyEventKind::Press {
+            return Action::Tick
+        }
+
+        match key.code {
+            KeyCode::Enter | KeyCode::Char('q') | KeyCode::Esc => return Action::CloseDetail,
+            KeyCode::Char('o')                     => return Action::OpenOutput,
+            KeyCode::Char('j') | KeyCode::Down     => detail.scroll_down(),
+            KeyCode::Char('k') | KeyCode::Up       => detail.scroll_up(),
+            _                                      => {},
+        }
+
+        Action::Tick
+    }
 }
@@ -1,12 +1,18 @@
 mod terminal;
 mod app;
+mod detail;
+#[allow(clippy::needless_return, clippy::redundant_static_lifetimes, mismatched_lifetime_syntaxes)]
 mod render;
 mod handler;
+mod output;
 mod spinner;
 
 use std::error::Error;
+use std::ffi::OsString;
+use std::path::PathBuf;
 
-use futures_lite::future::block_on;
+use async_io::block_on;
+use run_stars_lib::path::{Locations, Root};
 use terminal::Terminal;
 
 #[derive(argh::FromArgs)]
@@ -15,13 +21,56 @@ struct Args {
     /// dir
     #[argh(positional)]
     dir: Option<String>,
+
+    /// show the states of the system and of every user that can be read
+    #[argh(switch, short = 'a')]
+    all: bool,
+
+    /// directory for the runtime states, instead of $RUN_STARS_RUNTIME_DIR or $XDG_RUNTIME_HOME/run_stars
+    #[argh(option)]
+    runtime_dir: Option<PathBuf>,
+
+    /// directory for the persistent states, instead of $RUN_STARS_STATE_DIR or $XDG_STATE_HOME/run_stars
+    #[argh(option)]
+    state_dir: Option<PathBuf>,
+
+    /// separate set of states inside of the state directories, instead of $RUN_STARS_NAMESPACE
+    #[argh(option)]
+    namespace: Option<String>,
 }
 
 fn main() -> Result<(), Box<dyn Error>> {
     let args: Args = argh::from_env();
 
+    // Runners that are started from here have to write into the same locations
+    let mut runner_args: Vec<OsString> = Vec::new();
+
+    if let Some(p) = &args.runtime_dir {
+        runner_args.extend(["--runtime-dir".into(), p.into()]);
+    }
+
+    if let Some(p) = &args.state_dir {
+        runner_args.extend(["--state-dir".into(), p.into()]);
+    }
+
+    if let Some(namespace) = &args.namespace {
+        runner_args.extend(["--namespace".into(), namespace.into()]);
+    }
+
+    Root::configure(Locations {
+        runtime_dir: args.runtime_dir,
+        state_dir:   args.state_dir,
+        namespace:   args.namespace,
+    })?;
+
+    let roots = match args.all {
+        true  => Root::all(),
+        false => vec![Root::current().clone()],
+    };
+
     let mut terminal = Terminal::init().unwrap();
-    let res = block_on(app::run(&mut terminal, app::App::new(args.dir)?));
+    // Drives the reactor that inotify is registered with on this thread, a background driver misses wake-ups
+    let res = block_on(app::run(&mut terminal, app::App::new(args.dir, roots, runner_args)));
 
     if let Err(err) = res {
         println!("{err:?}");
@@ -0,0 +1,163 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::PathBuf;
+
+/// Amount of lines that are kept for the scrollback.
+const SCROLLBACK: usize = 10_000;
+
+pub struct Output {
+    pub title: String,
+    pub path:  PathBuf,
+    pub lines: VecDeque<String>,
+
+    partial: Vec<u8>,
+    offset:  u64,
+
+    pub watched: bool,
+
+    pub wrap: bool,
+    /// First visible row, output is followed when it's not set
+    pub top:  Option<usize>,
+
+    /// Layout of the last render, that is needed to move around
+    pub last_top: usize,
+    pub top_line: usize,
+    pub height:   usize,
+
+    pub search: Option<String>,
+    pub input:  Option<String>,
+    /// Line that should be scrolled into view on the next render
+    pub jump:   Option<usize>,
+}
+
+impl Output {
+    pub fn new(title: String, path: PathBuf) -> Self {
+        Output {
+            title,
+            path,
+            lines:    VecDeque::new(),
+            partial:  Vec::new(),
+            offset:   0,
+            watched:  false,
+            wrap:     false,
+            top:      None,
+            last_top: 0,
+            top_line: 0,
+            height:   0,
+            search:   None,
+            input:    None,
+            jump:     None,
+        }
+    }
+
+    /// Reads everything that was appended since the last read,
+    /// starts from the beginning if the file was truncated.
+    pub fn read(&mut self) -> io::Result<()> {
+        let mut fd = File::open(&self.path)?;
+        let len = fd.metadata()?.len();
+
+        if len < self.offset {
+            self.lines.clear();
+            self.partial.clear();
+            self.offset = 0;
+        }
+
+        fd.seek(SeekFrom::Start(self.offset))?;
+
+        let mut b = std::mem::take(&mut self.partial);
+        self.offset += fd.read_to_end(&mut b)? as u64;
+
+        let mut lines = b.split(|&c| c == b'\n');
+
+        // Last part is either empty or a line that isn't finished yet
+        let last = lines.next_back().unwrap_or_default().to_vec();
+
+        lines.for_each(|l| self.lines.push_back(sanitize(l)));
+
+        if self.lines.len() > SCROLLBACK {
+            self.lines.drain(..self.lines.len() - SCROLLBACK);
+        }
+
+        self.partial = last;
+
+        Ok(())
+    }
+
+    /// Unfinished line is still shown, because it might be a prompt or a progress bar.
+    pub fn partial(&self) -> Option<String> {
+        match self.partial.is_empty() {
+            true  => None,
+            false => Some(sanitize(&self.partial)),
+        }
+    }
+
+    #[inline]
+    pub fn toggle_wrap(&mut self) {
+        self.wrap = !self.wrap;
+    }
+
+    pub fn scroll_up(&mut self, n: usize) {
+        self.top = Some(self.top.unwrap_or(self.last_top).saturating_sub(n));
+    }
+
+    pub fn scroll_down(&mut self, n: usize) {
+        self.top = self.top
+            .map(|top| top + n)
+            .filter(|&top| top < self.last_top);
+    }
+
+    #[inline]
+    pub fn scroll_top(&mut self) {
+        self.top = Some(0);
+    }
+
+    #[inline]
+    pub fn follow(&mut self) {
+        self.top = None;
+    }
+
+    pub fn submit_search(&mut self) {
+        self.search = self.input.take();
+
+        if !self.lines.is_empty() {
+            let before_top = (self.top_line + self.lines.len() - 1) % self.lines.len();
+            self.jump = self.find(before_top, true);
+        }
+    }
+
+    pub fn next_match(&mut self, forward: bool) {
+        if let Some(i) = self.find(self.top_line, forward) {
+            self.jump = Some(i);
+        }
+    }
+
+    /// Finds the next line that contains a search query, wrapping around the end.
+    pub fn find(&self, from: usize, forward: bool) -> Option<usize> {
+        let query = self.search.as_deref().filter(|q| !q.is_empty())?;
+        let len = self.lines.len();
+
+        (1..=len)
+            .map(|n| match forward {
+                true  => (from + n) % len,
+                false => (from + len - n % len) % len,
+            })
+            .find(|&i| self.lines[i].contains(query))
+    }
+}
+
+fn sanitize(l: &[u8]) -> String {
+    let l = String::from_utf8_lossy(l);
+
+    // Only the last redraw of a carriage returned line is visible on the terminal
+    let l = l.trim_end_matches('\r');
+    let l = l.rsplit('\r').next().unwrap_or_default();
+
+    l.replace('\t', "    ")
+        .chars()
+        .map(|c| match c.is_control() {
+            true  => '�',
+            false => c,
+        })
+        .collect()
+}
@@ -1,13 +1,18 @@
 use ratatui::Frame;
 use ratatui::layout::{Alignment, Constraint, Flex, Layout, Margin, Rect};
 use ratatui::style::{Style, Stylize};
-use ratatui::text::{Span, Text};
+use ratatui::text::{Line, Span, Text};
 use ratatui::widgets::block::{Position, Title};
 use ratatui::widgets::{Block, BorderType, Cell, HighlightSpacing, List, ListItem, ListState, Padding, Paragraph, Row, Scrollbar, ScrollbarOrientation, ScrollbarState, Table, TableState, Wrap};
 
-use run_stars_lib::Status;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 
-use crate::app::{Action, App, ErrorEntry, Severity, TaskEntry};
+use run_stars_lib::path::printable;
+use run_stars_lib::{format_duration, signal_name, Status};
+
+use crate::app::{format_time, Action, App, ErrorEntry, Severity, TaskEntry};
+use crate::detail::{format_memory, Detail};
+use crate::output::Output;
 use crate::spinner::{self, Spinner};
 
 mod theme {
@@ -17,6 +22,7 @@ mod theme {
     pub const COLOR_STATE_SUCCESS: Color = tailwind::GREEN.c400;
     pub const COLOR_STATE_FAILURE: Color = tailwind::ROSE.c500;
     pub const COLOR_STATE_RUNNING: Color = tailwind::YELLOW.c400;
+    pub const COLOR_STATE_CANCELLED: Color = tailwind::ORANGE.c400;
 
     pub const COLOR_BLOCK_TITLE: Color = tailwind::GRAY.c500;
     pub const COLOR_BORDER: Color = tailwind::GRAY.c800;
@@ -25,6 +31,8 @@ mod theme {
     pub const COLOR_RUNNING: Color = tailwind::INDIGO.c500;
     pub const COLOR_SELECTION: Color = tailwind::GRAY.c600;
     pub const COLOR_SELECTION_FOCUSED: Color = tailwind::PURPLE.c500;
+    pub const COLOR_SEARCH: Color = tailwind::AMBER.c300;
+    pub const COLOR_SEARCH_FOREGROUND: Color = tailwind::GRAY.c900;
 }
 
 pub fn render(f: &mut Frame, app: &mut App, tick: bool) {
@@ -34,12 +42,26 @@ pub fn render(f: &mut Frame, app: &mut App, tick: bool) {
     ])
     .split(f.size());
 
-    let task_panel_layout = Layout::vertical([ Constraint::Min(5), ])
+    let task_panel_layout = match app.output.is_some() || app.detail.is_some() {
+        true  => Layout::vertical([ Constraint::Percentage(40), Constraint::Percentage(60) ]),
+        false => Layout::vertical([ Constraint::Min(5), ]),
+    }
     .split(outer_layout[1]);
 
     render_state_list(f, app, outer_layout[0], tick);
     render_task_table(f, app, task_panel_layout[0], tick);
     render_scrollbar(f, &mut app.ui.task_table.scroll, task_panel_layout[0]);
+
+    if let Some(output) = app.output.as_mut() {
+        render_output(f, output, task_panel_layout[1]);
+    }
+
+    if let Some(detail) = app.detail.as_ref() {
+        if let Some(entry) = app.task_entries.get(detail.task) {
+            render_detail(f, detail, entry, task_panel_layout[1]);
+        }
+    }
+
     render_error(f, task_panel_layout[0], &app.last_error);
 }
 
@@ -52,20 +74,28 @@ fn render_state_list(f: &mut Frame, app: &mut App, area: Rect, tick: bool) {
     }
 
     let entries = app.state_entries.iter()
-        .map(|f| match f.state.running {
-            true => {
-                let mut text = Text::from(app.ui.state_list.spinner.current().fg(theme::COLOR_RUNNING));
+        .map(|f| {
+            let mut text = match f.state.running {
+                true => {
+                    let mut text = Text::from(app.ui.state_list.spinner.current().fg(theme::COLOR_RUNNING));
+
+                    text.push_span(Span::raw(" "));
+                    text.push_span(Span::raw(&f.name));
 
-                text.push_span(Span::raw(" "));
-                text.push_span(Span::raw(&f.name));
+                    if tick {
+                        app.ui.state_list.spinner.next();
+                    }
 
-                if tick {
-                    app.ui.state_list.spinner.next();
+                    text
                 }
+                false => Text::from(f.name.as_str()),
+            };
 
-                ListItem::new(text)
+            if let Some(owner) = &f.owner {
+                text.push_span(format!(" ({owner})").fg(theme::COLOR_BLOCK_TITLE));
             }
-            false => ListItem::new(f.name.as_str()),
+
+            ListItem::new(text)
         });
 
     let border = Block::bordered()
@@ -94,16 +124,17 @@ fn status(entry: &TaskEntry) -> Span {
     use run_stars_lib::Status;
 
     match entry.status {
-        Status::Success => "✓".fg(theme::COLOR_STATE_SUCCESS),
-        Status::Failure => "✗".fg(theme::COLOR_STATE_FAILURE),
-        Status::Running => entry.spinner.current().fg(theme::COLOR_STATE_RUNNING),
-        Status::Waiting => Span::raw("⧖"),
-        Status::Unknown => "?".fg(theme::COLOR_BLOCK_TITLE),
+        Status::Success   => "✓".fg(theme::COLOR_STATE_SUCCESS),
+        Status::Failure   => "✗".fg(theme::COLOR_STATE_FAILURE),
+        Status::Cancelled => "⊘".fg(theme::COLOR_STATE_CANCELLED),
+        Status::Running   => entry.spinner.current().fg(theme::COLOR_STATE_RUNNING),
+        Status::Waiting   => Span::raw("⧖"),
+        Status::Unknown   => "?".fg(theme::COLOR_BLOCK_TITLE),
     }
 }
 
 fn render_task_table(f: &mut Frame, app: &mut App, area: Rect, tick: bool) {
-    let key_legend = Title::from("(Esc) quit | (↑) move up | (↓) move down")
+    let key_legend = Title::from("(Esc) quit | (↑) move up | (↓) move down | (s) start | (x) kill | (c) cancel | (r) re-run | (o) output | (Enter) detail")
         .alignment(Alignment::Right)
         .position(Position::Bottom);
 
@@ -127,6 +158,7 @@ fn render_task_table(f: &mut Frame, app: &mut App, area: Rect, tick: bool) {
             Cell::from(status(entry)),
             Cell::from(entry.time.as_str()),
             Cell::from(entry.path.as_str()),
+            Cell::from(entry.task.message.as_str()),
         ]);
 
         row.fg(theme::COLOR_FOREGROUND).height(1)
@@ -145,6 +177,7 @@ fn render_task_table(f: &mut Frame, app: &mut App, area: Rect, tick: bool) {
         Constraint::Length(2),
         Constraint::Length(("Tue Jul 30 03:14:39 AM".len() + 1) as u16),
         Constraint::Length(50),
+        Constraint::Fill(1),
     ])
     .highlight_style(style_selected)
     .highlight_symbol(SELECTION_SYMBOL)
@@ -155,6 +188,234 @@ fn render_task_table(f: &mut Frame, app: &mut App, area: Rect, tick: bool) {
     f.render_stateful_widget(t, area, &mut app.ui.task_table.state);
 }
 
+fn render_output(f: &mut Frame, output: &mut Output, area: Rect) {
+    let key_legend = Title::from("(o) close | (w) wrap | (/) search | (n/N) next/previous | (g/G) top/follow")
+        .alignment(Alignment::Right)
+        .position(Position::Bottom);
+
+    let title = Title::from(output.title.as_str())
+        .alignment(Alignment::Left)
+        .position(Position::Bottom);
+
+    let border = Block::bordered()
+        .border_type(BorderType::Rounded)
+        .padding(Padding::horizontal(1))
+        .fg(theme::COLOR_BORDER)
+        .title(key_legend)
+        .title(title)
+        .title_style(Style::new().fg(theme::COLOR_BLOCK_TITLE));
+
+    let inner = border.inner(area);
+    f.render_widget(border, area);
+
+    let [text_area, input_area] = Layout::vertical([
+        Constraint::Fill(1),
+        Constraint::Length(output.input.is_some() as u16),
+    ])
+    .areas(inner);
+
+    let width = text_area.width as usize;
+    let height = text_area.height as usize;
+
+    let partial = output.partial();
+
+    let rows: Vec<(usize, &str)> = output.lines.iter()
+        .map(String::as_str)
+        .chain(partial.as_deref())
+        .enumerate()
+        .flat_map(|(i, l)| {
+            let rows = match output.wrap {
+                true  => wrap(l, width),
+                false => vec![l],
+            };
+
+            rows.into_iter().map(move |row| (i, row))
+        })
+        .collect();
+
+    let last_top = rows.len().saturating_sub(height);
+
+    let jump = output.jump.take()
+        .and_then(|line| rows.iter().position(|&(i, _)| i == line));
+
+    let top = match jump.or(output.top) {
+        Some(top) if top < last_top => Some(top),
+        _ => None,
+    };
+
+    let first = top.unwrap_or(last_top);
+    let top_line = rows.get(first).map_or(0, |&(i, _)| i);
+
+    match rows.is_empty() {
+        true => {
+            let p = Paragraph::new("No output")
+                .centered()
+                .fg(theme::COLOR_BLOCK_TITLE);
+
+            f.render_widget(p, center_of(text_area, Constraint::Fill(1), Constraint::Length(1)));
+        },
+        false => {
+            let query = output.search.as_deref().filter(|q| !q.is_empty());
+
+            let lines: Vec<_> = rows.iter()
+                .skip(first)
+                .take(height)
+                .map(|&(_, row)| highlight(row, query))
+                .collect();
+
+            f.render_widget(Paragraph::new(lines).fg(theme::COLOR_FOREGROUND), text_area);
+        },
+    }
+
+    if let Some(input) = &output.input {
+        let prompt = format!("/{input}");
+
+        f.set_cursor(input_area.x + prompt.width() as u16, input_area.y);
+        f.render_widget(Paragraph::new(prompt).fg(theme::COLOR_FOREGROUND), input_area);
+    }
+
+    output.top = top;
+    output.last_top = last_top;
+    output.top_line = top_line;
+    output.height = height;
+}
+
+fn render_detail(f: &mut Frame, detail: &Detail, entry: &TaskEntry, area: Rect) {
+    let key_legend = Title::from("(Enter) close | (↑) scroll up | (↓) scroll down | (o) output")
+        .alignment(Alignment::Right)
+        .position(Position::Bottom);
+
+    let title = Title::from(entry.path.as_str())
+        .alignment(Alignment::Left)
+        .position(Position::Bottom);
+
+    let border = Block::bordered()
+        .border_type(BorderType::Rounded)
+        .padding(Padding::horizontal(1))
+        .fg(theme::COLOR_BORDER)
+        .title(key_legend)
+        .title(title)
+        .title_style(Style::new().fg(theme::COLOR_BLOCK_TITLE));
+
+    let task = &entry.task;
+    let none = || String::from("-");
+
+    let field = |name: &'static str, value: String| Line::from(vec![
+        Span::raw(format!("{name:<12}")).fg(theme::COLOR_BLOCK_TITLE),
+        Span::raw(value),
+    ]);
+
+    let signal = task.signal.map(|signal| match signal_name(signal) {
+        Some(name) => format!("{signal} ({name})"),
+        None => signal.to_string(),
+    });
+
+    let usage = task.usage.map(|usage| (
+        format!("{} user, {} system", format_duration(usage.user), format_duration(usage.system)),
+        format_memory(usage.max_rss),
+    ));
+
+    let mut lines = vec![
+        Line::from(vec![
+            Span::raw(format!("{:<12}", "Status")).fg(theme::COLOR_BLOCK_TITLE),
+            status(entry),
+            Span::raw(format!(" {}", task.status.name())),
+        ]),
+        field("Command", printable(&task.path)),
+        field("Exit code", task.code.to_string()),
+        field("Signal", signal.unwrap_or_else(none)),
+        field("Started", task.start.map(format_time).unwrap_or_else(none)),
+        field("Ended", task.end.map(format_time).unwrap_or_else(none)),
+        field("Duration", task.duration().map(format_duration).unwrap_or_else(none)),
+        field("Attempts", task.attempts.to_string()),
+        field("CPU time", usage.as_ref().map(|u| u.0.clone()).unwrap_or_else(none)),
+        field("Memory", usage.map(|u| u.1).unwrap_or_else(none)),
+        field("Message", match task.message.is_empty() {
+            true  => none(),
+            false => task.message.clone(),
+        }),
+        Line::raw(""),
+        Line::raw("History").fg(theme::COLOR_BLOCK_TITLE),
+    ];
+
+    match detail.history.is_empty() {
+        true  => lines.push(Line::raw("No previous runs")),
+        false => lines.extend(detail.history.iter().map(|task| {
+            let duration = task.duration().map(format_duration).unwrap_or_else(none);
+
+            Line::raw(format!("{:<10}{}  code {:<3}  {}",
+                task.status.name(),
+                task.start.map(format_time).unwrap_or_else(|| format_time(task.time)),
+                task.code,
+                duration))
+        })),
+    }
+
+    lines.push(Line::raw(""));
+    lines.push(Line::raw("Output").fg(theme::COLOR_BLOCK_TITLE));
+
+    match detail.tail.is_empty() {
+        true  => lines.push(Line::raw("No output")),
+        false => lines.extend(detail.tail.iter().map(|l| Line::raw(l.as_str()))),
+    }
+
+    let p = Paragraph::new(lines)
+        .block(border)
+        .scroll((detail.scroll, 0))
+        .fg(theme::COLOR_FOREGROUND);
+
+    f.render_widget(p, area);
+}
+
+fn wrap(l: &str, width: usize) -> Vec<&str> {
+    if width == 0 || l.width() <= width {
+        return vec![l]
+    }
+
+    let mut rows = Vec::new();
+    let mut start = 0;
+    let mut row_width = 0;
+
+    for (i, c) in l.char_indices() {
+        let w = c.width().unwrap_or(0);
+
+        if row_width + w > width {
+            rows.push(&l[start..i]);
+            start = i;
+            row_width = 0;
+        }
+
+        row_width += w;
+    }
+
+    rows.push(&l[start..]);
+    rows
+}
+
+fn highlight<'a>(row: &'a str, query: Option<&str>) -> Line<'a> {
+    let Some(query) = query else {
+        return Line::raw(row)
+    };
+
+    let style = Style::new()
+        .fg(theme::COLOR_SEARCH_FOREGROUND)
+        .bg(theme::COLOR_SEARCH);
+
+    let mut spans = Vec::new();
+    let mut rest = row;
+
+    while let Some(i) = rest.find(query) {
+        spans.push(Span::raw(&rest[..i]));
+        spans.push(Span::styled(&rest[i..i + query.len()], style));
+
+        rest = &rest[i + query.len()..];
+    }
+
+    spans.push(Span::raw(rest));
+
+    Line::from(spans)
+}
+
 fn render_scrollbar(f: &mut Frame, scroll: &mut ScrollbarState, area: Rect) {
     const SCROLLBAR: Scrollbar = {
         Scrollbar::new(ScrollbarOrientation::VerticalRight)
@@ -342,6 +603,11 @@ impl TaskTable {
         self.state.select(None);
     }
 
+    #[inline]
+    pub fn selected(&self) -> Option<usize> {
+        self.state.selected()
+    }
+
     pub fn next(&mut self) -> usize {
         if self.len == 0 { return 0 }
 